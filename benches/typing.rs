@@ -0,0 +1,86 @@
+// 在大文件上测量单次按键的编辑耗时
+// 运行: cargo bench --bench typing
+// 文件大小可通过 MINI_EDITOR_BENCH_BYTES 调整，默认 1 GiB
+// 任一按键的平均耗时超过 MAX_KEYSTROKE 时以错误退出
+use std::{
+    env,
+    fs::{self, File},
+    io::{BufWriter, Write},
+    path::Path,
+    time::{Duration, Instant},
+};
+
+use mini_editor::{document::Document, Position};
+
+const KEYSTROKES: usize = 1000;
+// 远低于一帧的时间，输入不会有可感知的延迟
+const MAX_KEYSTROKE: Duration = Duration::from_millis(1);
+const LINE: &str = "The quick brown fox jumps over the lazy dog, 0123456789 äöü ❤ 漢字 end.\n";
+
+fn write_file(path: &Path, bytes: usize) -> Result<(), std::io::Error> {
+    let mut file = BufWriter::new(File::create(path)?);
+    let mut written = 0;
+    while written < bytes {
+        file.write_all(LINE.as_bytes())?;
+        written += LINE.len();
+    }
+    file.flush()
+}
+
+// 在 y 行依次输入、退格，返回每次按键的平均耗时
+fn type_at(document: &mut Document, y: usize) -> (Duration, Duration) {
+    let start = Instant::now();
+    for x in 0..KEYSTROKES {
+        document.insert(&Position { x, y }, 'a');
+    }
+    let insert = start.elapsed() / KEYSTROKES as u32;
+    let start = Instant::now();
+    for x in (0..KEYSTROKES).rev() {
+        document.delete(&Position { x, y });
+    }
+    let delete = start.elapsed() / KEYSTROKES as u32;
+    (insert, delete)
+}
+
+fn bench(label: &str, bytes: usize) -> Result<Duration, std::io::Error> {
+    let path = env::temp_dir().join(format!("mini-editor-bench-{label}.txt"));
+    write_file(&path, bytes)?;
+    let start = Instant::now();
+    let document = Document::open(path.to_str().unwrap_or_default());
+    fs::remove_file(&path)?;
+    let mut document = document?;
+    println!(
+        "{label}: {} bytes, {} lines, opened in {:?}",
+        bytes,
+        document.len(),
+        start.elapsed()
+    );
+    let mut worst = Duration::ZERO;
+    let len = document.len();
+    for (position, y) in [("first", 0), ("middle", len / 2), ("last", len - 1)] {
+        let (insert, delete) = type_at(&mut document, y);
+        println!("  {position:>6} line: insert {insert:?}/key, delete {delete:?}/key");
+        worst = worst.max(insert).max(delete);
+    }
+    Ok(worst)
+}
+
+fn main() -> Result<(), std::io::Error> {
+    let bytes = env::var("MINI_EDITOR_BENCH_BYTES")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(1 << 30);
+    let small = bench("small", 1 << 20)?;
+    let large = bench("large", bytes)?;
+    println!(
+        "worst keystroke: small {small:?}, large {large:?} ({:.2}x)",
+        large.as_secs_f64() / small.as_secs_f64()
+    );
+    let worst = small.max(large);
+    if worst > MAX_KEYSTROKE {
+        return Err(std::io::Error::other(format!(
+            "worst keystroke {worst:?} exceeds {MAX_KEYSTROKE:?}"
+        )));
+    }
+    Ok(())
+}
@@ -0,0 +1,486 @@
+use std::{
+    collections::HashMap,
+    env, fs, io,
+    path::{Path, PathBuf},
+};
+
+use crossterm::style::Color;
+use toml::{Table, Value};
+
+use crate::highlighting::Type;
+
+// 项目配置文件名，从当前目录向上查找
+const PROJECT_FILE: &str = ".mini-editor.toml";
+const DEFAULT_TAB_WIDTH: usize = 4;
+const DEFAULT_QUIT_TIMES: u8 = 3;
+
+// 行号显示方式，相对行号模式下当前行仍显示绝对行号
+#[derive(PartialEq, Eq, Clone, Copy)]
+pub enum LineNumbers {
+    Off,
+    Absolute,
+    Relative,
+}
+
+// 可以绑定到 Ctrl 组合键的命令
+#[derive(PartialEq, Eq, Clone, Copy)]
+pub enum Command {
+    Find,
+    Replace,
+    Copy,
+    Cut,
+    Paste,
+    ExpandSelection,
+    ParentFunction,
+    LineNumbers,
+    Wrap,
+    NextBuffer,
+    Buffers,
+    Window,
+    Open,
+    FindFile,
+    LineEnding,
+    Reopen,
+    Hex,
+    Undo,
+    Redo,
+    Save,
+    Quit,
+}
+
+// 命令、配置中的名称、帮助中的说明与默认按键，按帮助中的顺序排列
+const COMMANDS: [(Command, &str, &str, char); 21] = [
+    (Command::Find, "find", "find", 'f'),
+    (Command::Replace, "replace", "replace", 'r'),
+    (Command::Copy, "copy", "copy", 'c'),
+    (Command::Cut, "cut", "cut", 'k'),
+    (Command::Paste, "paste", "paste", 'v'),
+    (Command::ExpandSelection, "expand_selection", "expand selection", 'w'),
+    (Command::ParentFunction, "parent_function", "parent function", 'p'),
+    (Command::LineNumbers, "line_numbers", "line numbers", 'l'),
+    (Command::Wrap, "wrap", "wrap", 't'),
+    (Command::NextBuffer, "next_buffer", "next buffer", 'n'),
+    (Command::Buffers, "buffers", "buffer list", 'b'),
+    (Command::Window, "window", "window", 'g'),
+    (Command::Open, "open", "open", 'o'),
+    (Command::FindFile, "find_file", "find file", 'e'),
+    (Command::LineEnding, "line_ending", "line endings", 'd'),
+    (Command::Reopen, "reopen", "reopen with encoding", 'u'),
+    (Command::Hex, "hex", "hex view", 'a'),
+    (Command::Undo, "undo", "undo", 'z'),
+    (Command::Redo, "redo", "redo", 'y'),
+    (Command::Save, "save", "save", 's'),
+    (Command::Quit, "quit", "quit", 'x'),
+];
+
+// 界面与语法高亮的颜色
+pub struct Theme {
+    pub status_bg: Color,
+    pub status_fg: Color,
+    pub inactive_status_bg: Color,
+    pub match_bg: Color,
+    pub gutter_fg: Color,
+    pub current_line_number: Color,
+    number: Color,
+    string: Color,
+    comment: Color,
+    keyword: Color,
+    builtin: Color,
+    operator: Color,
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Self {
+            status_bg: Color::Rgb { r: 239, g: 239, b: 239 },
+            status_fg: Color::Rgb { r: 63, g: 63, b: 63 },
+            inactive_status_bg: Color::Rgb { r: 150, g: 150, b: 150 },
+            match_bg: Color::Rgb { r: 38, g: 139, b: 210 },
+            gutter_fg: Color::Rgb { r: 120, g: 120, b: 120 },
+            current_line_number: Color::Rgb { r: 255, g: 215, b: 0 },
+            number: Type::Number.to_color(),
+            string: Type::String.to_color(),
+            comment: Type::Comment.to_color(),
+            keyword: Type::Keyword.to_color(),
+            builtin: Type::Builtin.to_color(),
+            operator: Type::Operator.to_color(),
+        }
+    }
+}
+
+impl Theme {
+    pub fn syntax(&self, hl_type: Type) -> Color {
+        match hl_type {
+            Type::Number => self.number,
+            Type::String => self.string,
+            Type::Comment => self.comment,
+            Type::Keyword => self.keyword,
+            Type::Builtin => self.builtin,
+            Type::Operator => self.operator,
+            Type::None => Color::Reset,
+        }
+    }
+    fn color_mut(&mut self, name: &str) -> Option<&mut Color> {
+        Some(match name {
+            "status_bg" => &mut self.status_bg,
+            "status_fg" => &mut self.status_fg,
+            "inactive_status_bg" => &mut self.inactive_status_bg,
+            "match_bg" => &mut self.match_bg,
+            "gutter_fg" => &mut self.gutter_fg,
+            "current_line_number" => &mut self.current_line_number,
+            "number" => &mut self.number,
+            "string" => &mut self.string,
+            "comment" => &mut self.comment,
+            "keyword" => &mut self.keyword,
+            "builtin" => &mut self.builtin,
+            "operator" => &mut self.operator,
+            _ => return None,
+        })
+    }
+}
+
+pub struct Config {
+    pub tab_width: usize,
+    pub line_numbers: LineNumbers,
+    pub soft_wrap: bool,
+    pub backup: bool,
+    // 有未保存修改时退出需要连按的次数
+    pub quit_times: u8,
+    pub theme: Theme,
+    keys: HashMap<char, Command>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            tab_width: DEFAULT_TAB_WIDTH,
+            line_numbers: LineNumbers::Off,
+            soft_wrap: false,
+            backup: false,
+            quit_times: DEFAULT_QUIT_TIMES,
+            theme: Theme::default(),
+            keys: COMMANDS
+                .iter()
+                .map(|(command, _, _, key)| (*key, *command))
+                .collect(),
+        }
+    }
+}
+
+impl Config {
+    // 先读用户配置，再用项目配置覆盖，返回配置与所有错误信息
+    pub fn load() -> (Self, Vec<String>) {
+        let mut config = Self::default();
+        let mut errors = Vec::new();
+        for path in [user_path(), project_path()].into_iter().flatten() {
+            if let Err(error) = config.merge_file(&path) {
+                errors.push(format!("{}: {error}", path.display()));
+            }
+        }
+        // 保证总能退出
+        if config.key(Command::Quit).is_none() {
+            config.keys.insert('x', Command::Quit);
+            errors.push("quit must be bound to a key, using ctrl-x".to_string());
+        }
+        (config, errors)
+    }
+    // 读取一个配置文件，出错的选项跳过，其余照常生效
+    fn merge_file(&mut self, path: &Path) -> Result<(), String> {
+        let contents = match fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(error) => return Err(error.to_string()),
+        };
+        self.merge(&contents)
+    }
+    // 解析配置文件内容并覆盖已有设置
+    fn merge(&mut self, contents: &str) -> Result<(), String> {
+        let table: Table = toml::from_str(contents).map_err(|error| {
+            let line = error
+                .span()
+                .map_or(0, |span| contents[..span.start].matches('\n').count() + 1);
+            format!("line {line}: {}", error.message())
+        })?;
+        let errors = table
+            .iter()
+            .filter_map(|(name, value)| self.set(name, value).err())
+            .collect();
+        join_errors(errors)
+    }
+    fn set(&mut self, name: &str, value: &Value) -> Result<(), String> {
+        let invalid = |expected: &str| format!("{name} must be {expected}");
+        match name {
+            "tab_width" => {
+                let width = value.as_integer().filter(|width| (1..=16).contains(width));
+                self.tab_width = width.ok_or_else(|| invalid("an integer from 1 to 16"))? as usize;
+            }
+            "line_numbers" => {
+                self.line_numbers = value
+                    .as_str()
+                    .and_then(parse_line_numbers)
+                    .ok_or_else(|| invalid("\"off\", \"absolute\" or \"relative\""))?;
+            }
+            "wrap" => self.soft_wrap = value.as_bool().ok_or_else(|| invalid("true or false"))?,
+            "backup" => self.backup = value.as_bool().ok_or_else(|| invalid("true or false"))?,
+            "quit_times" => {
+                let times = value.as_integer().filter(|times| (0..=10).contains(times));
+                self.quit_times = times.ok_or_else(|| invalid("an integer from 0 to 10"))? as u8;
+            }
+            "colors" => self.set_colors(value.as_table().ok_or_else(|| invalid("a table"))?)?,
+            "keys" => self.set_keys(value.as_table().ok_or_else(|| invalid("a table"))?)?,
+            _ => return Err(format!("unknown option {name}")),
+        }
+        Ok(())
+    }
+    fn set_colors(&mut self, colors: &Table) -> Result<(), String> {
+        let mut errors = Vec::new();
+        for (name, value) in colors {
+            let Some(slot) = self.theme.color_mut(name) else {
+                errors.push(format!("unknown color colors.{name}"));
+                continue;
+            };
+            match value.as_str().and_then(parse_color) {
+                Some(color) => *slot = color,
+                None => errors.push(format!(
+                    "colors.{name} must be \"#rrggbb\" or a color name such as \"dark_blue\""
+                )),
+            }
+        }
+        join_errors(errors)
+    }
+    // keys 表形如 "ctrl-s" = "save"，"none" 取消绑定
+    fn set_keys(&mut self, keys: &Table) -> Result<(), String> {
+        let mut errors = Vec::new();
+        for (name, value) in keys {
+            let key = match parse_key(name) {
+                Ok(key) => key,
+                Err(error) => {
+                    errors.push(format!("keys.{name}: {error}"));
+                    continue;
+                }
+            };
+            match value.as_str() {
+                Some("none") => {
+                    self.keys.remove(&key);
+                }
+                Some(command) => match COMMANDS.iter().find(|(_, name, _, _)| *name == command) {
+                    Some((command, _, _, _)) => {
+                        self.keys.insert(key, *command);
+                    }
+                    None => errors.push(format!("keys.{name}: unknown command {command}")),
+                },
+                None => errors.push(format!("keys.{name} must be a command name")),
+            }
+        }
+        join_errors(errors)
+    }
+    // Ctrl 加字母所绑定的命令
+    pub fn command(&self, key: char) -> Option<Command> {
+        self.keys.get(&key.to_ascii_lowercase()).copied()
+    }
+    // 命令的第一个按键，用于提示信息
+    pub fn key(&self, command: Command) -> Option<String> {
+        let mut keys: Vec<char> = self
+            .keys
+            .iter()
+            .filter(|(_, bound)| **bound == command)
+            .map(|(key, _)| *key)
+            .collect();
+        keys.sort_unstable();
+        keys.first().map(|key| format!("Ctrl-{}", key.to_ascii_uppercase()))
+    }
+    // 按当前绑定生成的帮助信息
+    pub fn help(&self) -> String {
+        let entries: Vec<String> = COMMANDS
+            .iter()
+            .filter_map(|(command, _, description, _)| {
+                let mut keys: Vec<String> = self
+                    .keys
+                    .iter()
+                    .filter(|(_, bound)| *bound == command)
+                    .map(|(key, _)| key.to_ascii_uppercase().to_string())
+                    .collect();
+                if keys.is_empty() {
+                    return None;
+                }
+                keys.sort();
+                Some(format!("Ctrl-{} = {description}", keys.join("/")))
+            })
+            .collect();
+        format!("HELP: {}", entries.join(" | "))
+    }
+}
+
+fn join_errors(errors: Vec<String>) -> Result<(), String> {
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors.join("; "))
+    }
+}
+
+// $XDG_CONFIG_HOME/mini-editor/config.toml，未设置时用 ~/.config
+fn user_path() -> Option<PathBuf> {
+    let config_home = env::var_os("XDG_CONFIG_HOME")
+        .filter(|path| !path.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+    Some(config_home.join("mini-editor").join("config.toml"))
+}
+
+fn project_path() -> Option<PathBuf> {
+    let current = env::current_dir().ok()?;
+    current
+        .ancestors()
+        .map(|directory| directory.join(PROJECT_FILE))
+        .find(|path| path.is_file())
+}
+
+fn parse_line_numbers(mode: &str) -> Option<LineNumbers> {
+    match mode {
+        "off" => Some(LineNumbers::Off),
+        "absolute" => Some(LineNumbers::Absolute),
+        "relative" => Some(LineNumbers::Relative),
+        _ => None,
+    }
+}
+
+// "#rrggbb" 或 crossterm 的颜色名
+fn parse_color(value: &str) -> Option<Color> {
+    if let Some(hex) = value.strip_prefix('#') {
+        if hex.len() != 6 || !hex.is_ascii() {
+            return None;
+        }
+        let channel = |index: usize| u8::from_str_radix(&hex[index..index + 2], 16).ok();
+        return Some(Color::Rgb {
+            r: channel(0)?,
+            g: channel(2)?,
+            b: channel(4)?,
+        });
+    }
+    Color::try_from(value).ok()
+}
+
+// "ctrl-s" 形式的按键，只支持 Ctrl 加字母
+// 终端把 Ctrl-H、Ctrl-I、Ctrl-J、Ctrl-M 作为退格、Tab 与回车发送，不能绑定
+fn parse_key(name: &str) -> Result<char, String> {
+    let key = name.to_ascii_lowercase();
+    let mut letters = key.strip_prefix("ctrl-").unwrap_or_default().chars();
+    let letter = match (letters.next(), letters.next()) {
+        (Some(letter), None) if letter.is_ascii_lowercase() => letter,
+        _ => return Err("keys must look like \"ctrl-s\"".to_string()),
+    };
+    let sent_as = match letter {
+        'h' => "Backspace",
+        'i' => "Tab",
+        'j' | 'm' => "Enter",
+        _ => return Ok(letter),
+    };
+    Err(format!("terminals send {name} as {sent_as}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn merged(contents: &str) -> (Config, Result<(), String>) {
+        let mut config = Config::default();
+        let result = config.merge(contents);
+        (config, result)
+    }
+
+    #[test]
+    fn parse_keys() {
+        assert_eq!(parse_key("ctrl-s"), Ok('s'));
+        assert_eq!(parse_key("Ctrl-Q"), Ok('q'));
+        assert!(parse_key("ctrl-").is_err());
+        assert!(parse_key("ctrl-ab").is_err());
+        assert!(parse_key("ctrl-1").is_err());
+        assert!(parse_key("alt-s").is_err());
+        assert!(parse_key("s").is_err());
+        // 终端无法区分的组合键
+        assert_eq!(parse_key("ctrl-h"), Err("terminals send ctrl-h as Backspace".to_string()));
+        assert_eq!(parse_key("Ctrl-I"), Err("terminals send Ctrl-I as Tab".to_string()));
+        assert!(parse_key("ctrl-j").is_err());
+        assert!(parse_key("ctrl-m").is_err());
+    }
+
+    #[test]
+    fn parse_colors() {
+        assert_eq!(parse_color("#ff8000"), Some(Color::Rgb { r: 255, g: 128, b: 0 }));
+        assert_eq!(parse_color("dark_blue"), Some(Color::DarkBlue));
+        assert_eq!(parse_color("#ff80"), None);
+        assert_eq!(parse_color("#gg0000"), None);
+        assert_eq!(parse_color("#ééé"), None);
+        assert_eq!(parse_color("no-such-color"), None);
+    }
+
+    #[test]
+    fn defaults() {
+        let config = Config::default();
+        assert_eq!(config.tab_width, 4);
+        assert!(config.line_numbers == LineNumbers::Off);
+        assert!(config.command('s') == Some(Command::Save));
+        assert_eq!(config.key(Command::Quit).as_deref(), Some("Ctrl-X"));
+        assert!(config.help().starts_with("HELP: Ctrl-F = find | Ctrl-R = replace"));
+    }
+
+    #[test]
+    fn options_are_validated() {
+        let (config, result) = merged("tab_width = 8\nline_numbers = \"relative\"\nwrap = true\nbackup = true\nquit_times = 0");
+        assert!(result.is_ok());
+        assert_eq!(config.tab_width, 8);
+        assert!(config.line_numbers == LineNumbers::Relative);
+        assert!(config.soft_wrap && config.backup);
+        assert_eq!(config.quit_times, 0);
+
+        let (config, result) = merged("tab_width = 0\nquit_times = 11\nwrap = \"yes\"\nbogus = 1\nbackup = true");
+        let errors = result.unwrap_err();
+        assert!(errors.contains("tab_width must be an integer from 1 to 16"));
+        assert!(errors.contains("quit_times must be an integer from 0 to 10"));
+        assert!(errors.contains("wrap must be true or false"));
+        assert!(errors.contains("unknown option bogus"));
+        // 出错的选项保持默认，其余照常生效
+        assert_eq!(config.tab_width, 4);
+        assert!(config.backup);
+    }
+
+    #[test]
+    fn syntax_errors_report_the_line() {
+        let (_, result) = merged("wrap = true\ntab_width = \n");
+        assert!(result.unwrap_err().starts_with("line 2: "));
+        // 出错处在行首
+        let (_, result) = merged("wrap = true\ntab_width = 4\n= 1\n");
+        assert!(result.unwrap_err().starts_with("line 3: "));
+    }
+
+    #[test]
+    fn colors_override_the_theme() {
+        let (config, result) = merged("[colors]\nstatus_bg = \"#010203\"\nkeyword = \"red\"\nnope = \"red\"\nstring = \"#12\"");
+        assert_eq!(config.theme.status_bg, Color::Rgb { r: 1, g: 2, b: 3 });
+        assert_eq!(config.theme.syntax(Type::Keyword), Color::Red);
+        assert_eq!(config.theme.syntax(Type::String), Type::String.to_color());
+        let errors = result.unwrap_err();
+        assert!(errors.contains("unknown color colors.nope"));
+        assert!(errors.contains("colors.string must be"));
+    }
+
+    #[test]
+    fn keys_rebind_and_unbind() {
+        let (config, result) = merged(
+            "[keys]\n\"ctrl-q\" = \"quit\"\n\"ctrl-x\" = \"none\"\n\"ctrl-s\" = \"find\"\n\"meta-a\" = \"save\"\n\"ctrl-k\" = \"fly\"\n\"ctrl-m\" = \"save\"",
+        );
+        assert!(config.command('q') == Some(Command::Quit));
+        assert!(config.command('x').is_none());
+        assert!(config.command('S') == Some(Command::Find));
+        assert_eq!(config.key(Command::Quit).as_deref(), Some("Ctrl-Q"));
+        assert_eq!(config.key(Command::Find).as_deref(), Some("Ctrl-F"));
+        assert!(config.key(Command::Save).is_none());
+        assert!(config.help().contains("Ctrl-F/S = find"));
+        assert!(!config.help().contains("= save"));
+        let errors = result.unwrap_err();
+        assert!(errors.contains("keys.meta-a: keys must look like"));
+        assert!(errors.contains("keys.ctrl-k: unknown command fly"));
+        assert!(errors.contains("keys.ctrl-m: terminals send ctrl-m as Enter"));
+    }
+}
@@ -1,93 +1,1376 @@
-use std::{fs, io::Write};
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    fmt,
+    fs::{self, OpenOptions},
+    hash::{DefaultHasher, Hasher},
+    io::{self, BufWriter, Write},
+    path::{Path, PathBuf},
+    process,
+    rc::Rc,
+    time::SystemTime,
+};
 
-use crate::{row::Row, Position};
+use regex::Regex;
+use ropey::{Rope, RopeBuilder};
+use unicode_segmentation::UnicodeSegmentation;
+
+use tree_sitter::InputEdit;
+
+use crate::{
+    encoding::FileEncoding,
+    filetype::FileType,
+    hex,
+    highlighting::State,
+    history::{Edit, History},
+    row::Row,
+    swap,
+    syntax_tree::{self, SyntaxTree},
+    Position, SearchDirection,
+};
+
+// 行缓存的上限，超过时整体清空
+const ROW_CACHE_LINES: usize = 4096;
+
+// 文件使用的换行符
+#[derive(Default, Clone, Copy, PartialEq, Eq)]
+pub enum LineEnding {
+    #[default]
+    Lf,
+    CrLf,
+    Cr,
+}
+
+impl LineEnding {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Lf => "\n",
+            Self::CrLf => "\r\n",
+            Self::Cr => "\r",
+        }
+    }
+    // 按出现次数最多的换行符判断，没有换行时为 LF
+    fn detect(contents: &str) -> Self {
+        let crlf = contents.matches("\r\n").count();
+        let lf = contents.matches('\n').count() - crlf;
+        let cr = contents.matches('\r').count() - crlf;
+        if crlf > lf && crlf >= cr {
+            Self::CrLf
+        } else if cr > lf && cr > crlf {
+            Self::Cr
+        } else {
+            Self::Lf
+        }
+    }
+}
+
+impl fmt::Display for LineEnding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Lf => "LF",
+            Self::CrLf => "CRLF",
+            Self::Cr => "CR",
+        })
+    }
+}
+
+// 文件在磁盘上的外部修改
+pub enum DiskChange {
+    Modified,
+    Deleted,
+}
+
+// 打开或保存时磁盘上文件的状态
+#[derive(Clone, Copy, PartialEq, Eq)]
+struct DiskState {
+    modified: Option<SystemTime>,
+    len: u64,
+    hash: u64,
+}
+
+impl DiskState {
+    fn new(metadata: &fs::Metadata, hash: u64) -> Self {
+        Self {
+            modified: metadata.modified().ok(),
+            len: metadata.len(),
+            hash,
+        }
+    }
+}
+
+fn hash(bytes: &[u8]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    hasher.write(bytes);
+    hasher.finish()
+}
+
+// 边写边计算内容的哈希
+struct HashWriter<'a> {
+    writer: &'a mut dyn Write,
+    hasher: DefaultHasher,
+}
+
+impl Write for HashWriter<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.writer.write(buf)?;
+        self.hasher.write(&buf[..written]);
+        Ok(written)
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
 
 #[derive(Default)]
 pub struct Document {
-    rows: Vec<Row>,
+    // 每行以 \n 结尾
+    text: Rope,
+    // 切分过字位的行，由 splice 使之失效
+    rows: RefCell<HashMap<usize, Rc<Row>>>,
     pub file_name: Option<String>,
-    dirty: bool,
+    history: History,
+    file_type: FileType,
+    // 每行行尾的高亮状态，只缓存到绘制过的最后一行
+    states: Vec<State>,
+    // 有 tree-sitter 语法的文件类型使用语法树高亮
+    syntax: Option<SyntaxTree>,
+    // 保存时使用的换行符，内部始终以 \n 分行
+    line_ending: LineEnding,
+    // 文件末尾没有换行符
+    missing_newline: bool,
+    // 上次保存或打开时的换行符设置，用于判断是否有修改
+    saved_format: (LineEnding, bool),
+    // 文件混用了无法原样保留的换行符，保存时统一为 line_ending
+    mixed_line_endings: bool,
+    // 保存时按原编码写回
+    encoding: FileEncoding,
+    // 含有无法解码的字节，只能查看不能修改
+    read_only: bool,
+    // 十六进制模式下的原始字节，此时 text 为逐行的十六进制转储
+    bytes: Option<Vec<u8>>,
+    bytes_modified: bool,
+    // 每次修改加一，与 swap_version 比较判断交换文件是否过期
+    version: usize,
+    // 上次写入交换文件时的版本，没有交换文件时为 None
+    swap_version: Option<usize>,
+    // 打开或保存时磁盘上的状态，用于发现外部修改
+    disk: Option<DiskState>,
+    // 用户选择保留自己修改时磁盘上的状态，同样的外部修改不再提示
+    ignored_disk: Option<DiskState>,
 }
 
 impl Document {
+    // 二进制文件自动以十六进制模式打开
     pub fn open(filename: &str) -> Result<Self, std::io::Error> {
-        let contents = fs::read_to_string(filename)?;
-        let mut rows = Vec::new();
-        for value in contents.lines() {
-            rows.push(Row::from(value));
+        let (bytes, disk) = read_file(filename)?;
+        let mut document = if hex::is_binary(&bytes) {
+            Self::from_hex(filename, bytes)
+        } else {
+            Self::from_bytes(filename, &bytes, None)
+        };
+        document.disk = Some(disk);
+        Ok(document)
+    }
+    // 按指定编码打开，None 时自动检测
+    pub fn open_with_encoding(filename: &str, encoding: Option<FileEncoding>) -> Result<Self, std::io::Error> {
+        let (bytes, disk) = read_file(filename)?;
+        let mut document = Self::from_bytes(filename, &bytes, encoding);
+        document.disk = Some(disk);
+        Ok(document)
+    }
+    pub fn open_hex(filename: &str) -> Result<Self, std::io::Error> {
+        let (bytes, disk) = read_file(filename)?;
+        let mut document = Self::from_hex(filename, bytes);
+        document.disk = Some(disk);
+        Ok(document)
+    }
+    // 按原来的模式与编码重新读取文件，放弃未保存的修改
+    pub fn reload(&mut self) -> Result<(), std::io::Error> {
+        let Some(file_name) = self.file_name.clone() else {
+            return Ok(());
+        };
+        let document = if self.is_hex() {
+            Self::open_hex(&file_name)?
+        } else {
+            Self::open_with_encoding(&file_name, Some(self.encoding))?
+        };
+        self.remove_swap()?;
+        *self = document;
+        Ok(())
+    }
+    // 磁盘上文件当前的状态，大小与修改时间都没变时不再读取内容
+    fn disk_state(&self) -> Result<Option<DiskState>, io::Error> {
+        let Some(file_name) = &self.file_name else {
+            return Ok(None);
+        };
+        let metadata = match fs::metadata(file_name) {
+            Ok(metadata) => metadata,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(error) => return Err(error),
+        };
+        let unchanged = |disk: &DiskState| {
+            disk.modified == metadata.modified().ok() && disk.len == metadata.len()
+        };
+        if let Some(disk) = self.disk.filter(unchanged) {
+            return Ok(Some(disk));
+        }
+        Ok(Some(DiskState::new(&metadata, hash(&fs::read(file_name)?))))
+    }
+    // 检查文件是否被其他程序修改或删除，每次变化只报告一次
+    pub fn check_disk(&mut self) -> Option<DiskChange> {
+        let state = self.disk_state().ok()?;
+        match (self.disk, state) {
+            (Some(_), None) => {
+                self.disk = None;
+                Some(DiskChange::Deleted)
+            }
+            (None, None) => None,
+            // 只是修改时间变了，内容相同
+            (Some(disk), Some(state)) if disk.hash == state.hash => {
+                self.disk = Some(state);
+                None
+            }
+            (_, Some(state)) if self.ignored_disk == Some(state) => None,
+            (_, Some(_)) => Some(DiskChange::Modified),
+        }
+    }
+    // 保留自己的修改，忽略磁盘上当前的版本
+    pub fn ignore_disk_change(&mut self) {
+        self.ignored_disk = self.disk_state().ok().flatten();
+    }
+    // 磁盘上的内容与打开或上次保存时不同，保存会覆盖别人的修改
+    pub fn conflicts_with_disk(&self) -> bool {
+        matches!(
+            (self.disk, self.disk_state()),
+            (Some(disk), Ok(Some(state))) if disk.hash != state.hash
+        )
+    }
+    fn from_hex(filename: &str, bytes: Vec<u8>) -> Self {
+        Self {
+            text: Rope::from(hex::dump(&bytes)),
+            file_name: Some(filename.to_string()),
+            bytes: Some(bytes),
+            ..Self::default()
+        }
+    }
+    // 不关联文件的文档，用于显示比较结果等
+    pub fn from_text(text: &str) -> Self {
+        let mut builder = RopeBuilder::new();
+        for line in text.lines() {
+            builder.append(line);
+            builder.append("\n");
+        }
+        Self {
+            text: builder.finish(),
+            ..Self::default()
         }
-        Ok(Self {
-            rows,
+    }
+    pub fn text(&self) -> String {
+        self.text.to_string()
+    }
+    fn from_bytes(filename: &str, bytes: &[u8], encoding: Option<FileEncoding>) -> Self {
+        let encoding = encoding.unwrap_or_else(|| FileEncoding::detect(bytes));
+        let (contents, lossless) = encoding.decode(bytes);
+        let line_ending = LineEnding::detect(&contents);
+        // 内部只能以 \n 分行，CRLF 文件里单独的 \n 与 CR 文件里的 \n 保存时会被统一
+        let mixed_line_endings = match line_ending {
+            LineEnding::Lf => false,
+            LineEnding::CrLf => contents.matches('\n').count() > contents.matches("\r\n").count(),
+            LineEnding::Cr => contents.contains('\n'),
+        };
+        let contents = if line_ending == LineEnding::Cr {
+            contents.replace("\r\n", "\n").replace('\r', "\n")
+        } else {
+            contents
+        };
+        let missing_newline = !contents.is_empty() && !contents.ends_with('\n');
+        // LF 与 CRLF 文件里零星的 \r 原样保留在行内
+        let mut builder = RopeBuilder::new();
+        for value in contents.split_inclusive('\n') {
+            let value = match (value.strip_suffix('\n'), line_ending) {
+                (Some(line), LineEnding::CrLf) => line.strip_suffix('\r').unwrap_or(line),
+                (Some(line), _) => line,
+                // 没有换行符的最后一行
+                (None, _) => value,
+            };
+            builder.append(value);
+            builder.append("\n");
+        }
+        let file_type = FileType::from(filename);
+        Self {
+            text: builder.finish(),
             file_name: Some(filename.to_string()),
-            dirty: false,
-        })
+            history: History::default(),
+            syntax: SyntaxTree::new(&file_type.to_string()),
+            file_type,
+            states: Vec::new(),
+            line_ending,
+            missing_newline,
+            saved_format: (line_ending, missing_newline),
+            mixed_line_endings,
+            encoding,
+            read_only: !lossless,
+            ..Self::default()
+        }
     }
-    pub fn row(&self, index: usize) -> Option<&Row> {
-        self.rows.get(index)
+    pub fn row(&self, index: usize) -> Option<Rc<Row>> {
+        if index >= self.len() {
+            return None;
+        }
+        let mut rows = self.rows.borrow_mut();
+        if let Some(row) = rows.get(&index) {
+            return Some(Rc::clone(row));
+        }
+        let mut line = String::from(self.text.line(index));
+        line.pop();
+        let row = Rc::new(Row::from(line));
+        if rows.len() >= ROW_CACHE_LINES {
+            rows.clear();
+        }
+        rows.insert(index, Rc::clone(&row));
+        Some(row)
+    }
+    // 带语法高亮的行，用于绘制
+    pub fn highlighted_row(&mut self, index: usize) -> Option<Row> {
+        let mut row = Row::clone(&*self.row(index)?);
+        if let Some(syntax) = &mut self.syntax {
+            let start = self.text.line_to_byte(index);
+            let end = self.text.line_to_byte(index + 1);
+            let highlights = syntax.highlights(&self.text, start..end);
+            row.set_highlighting(
+                highlights
+                    .into_iter()
+                    .map(|(range, hl_type)| {
+                        (range.start.saturating_sub(start)..range.end.saturating_sub(start), hl_type)
+                    }),
+            );
+            return Some(row);
+        }
+        let state = self.start_state(index);
+        let state = row.highlight(self.file_type.highlighting_options(), state);
+        if index == self.states.len() {
+            self.states.push(state);
+        }
+        Some(row)
+    }
+    // index 行开头的高亮状态，必要时向后补算缓存
+    fn start_state(&mut self, index: usize) -> State {
+        while self.states.len() < index {
+            let y = self.states.len();
+            let state = self.states.last().cloned().unwrap_or_default();
+            let state = self.row(y).map_or(State::Normal, |row| {
+                row.end_state(self.file_type.highlighting_options(), state)
+            });
+            self.states.push(state);
+        }
+        index
+            .checked_sub(1)
+            .and_then(|y| self.states.get(y))
+            .cloned()
+            .unwrap_or_default()
+    }
+    // 编辑后从 from 行起重新计算行尾状态，越过 changed 行后状态不变即停止
+    fn update_states(&mut self, from: usize, changed: usize) {
+        for y in from..self.states.len() {
+            let state = y
+                .checked_sub(1)
+                .and_then(|y| self.states.get(y))
+                .cloned()
+                .unwrap_or_default();
+            let state = self.row(y).map_or(State::Normal, |row| {
+                row.end_state(self.file_type.highlighting_options(), state)
+            });
+            if y > changed && self.states[y] == state {
+                break;
+            }
+            self.states[y] = state;
+        }
+    }
+    pub fn file_type(&self) -> &FileType {
+        &self.file_type
     }
     pub fn is_empty(&self) -> bool {
-        self.rows.is_empty()
+        self.text.len_chars() == 0
     }
     pub fn len(&self) -> usize {
-        self.rows.len()
+        self.text.len_lines() - 1
+    }
+    // 位置转换为字符下标
+    fn char_index(&self, at: &Position) -> usize {
+        let byte = self.row(at.y).map_or(0, |row| row.byte_index(at.x));
+        self.text.byte_to_char(self.text.line_to_byte(at.y) + byte)
     }
     pub fn insert(&mut self, at: &Position, c: char) {
         if at.y > self.len() {
             return;
         }
-        self.dirty=true;
         if c == '\n' {
             self.insert_newline(at);
             return;
         }
+        let edit = Edit::Insert {
+            at: at.clone(),
+            text: c.to_string(),
+        };
         if at.y == self.len() {
-            let mut row = Row::default();
-            row.insert(0, c);
-            self.rows.push(row);
-        } else{
-            let row = self.rows.get_mut(at.y).unwrap();
-            row.insert(at.x, c);
+            // 在末尾追加新行，与插入合为一步
+            self.history.seal();
+            self.append_line();
+            self.record_grouped(edit);
+            return;
         }
+        self.record(edit);
     }
-    fn insert_newline(&mut self, at: &Position) {
-        if at.y >= self.len() {
-            return;
+    // 一次插入多行文本，作为一步记录，返回插入结束的位置
+    pub fn insert_str(&mut self, at: &Position, text: &str) -> Position {
+        let mut position = at.clone();
+        if at.y > self.len() || text.is_empty() {
+            return position;
         }
+        self.history.seal();
         if at.y == self.len() {
-            self.rows.push(Row::default());
+            self.append_line();
+        }
+        for (index, line) in text.split('\n').enumerate() {
+            if index > 0 {
+                self.record_grouped(Edit::Split {
+                    at: position.clone(),
+                });
+                position = Position {
+                    x: 0,
+                    y: position.y + 1,
+                };
+            }
+            if !line.is_empty() {
+                let edit = Edit::Insert {
+                    at: position.clone(),
+                    text: line.to_string(),
+                };
+                position = edit.end();
+                self.record_grouped(edit);
+            }
+        }
+        self.history.seal();
+        position
+    }
+    // 拆分最后一行的行尾以追加一个空行，空文档则写入第一行的换行符
+    fn append_line(&mut self) {
+        let at = self.len().checked_sub(1).map_or_else(Position::default, |y| Position {
+            x: self.row(y).map_or(0, |row| row.len()),
+            y,
+        });
+        self.record_grouped(Edit::Split { at });
+    }
+    // 取出 [start, end) 区间的文本
+    pub fn text_range(&self, start: &Position, end: &Position) -> String {
+        let start = self.char_index(start);
+        let end = self.char_index(end).max(start);
+        self.text.slice(start..end).to_string()
+    }
+    fn insert_newline(&mut self, at: &Position) {
+        if at.y >= self.len() {
             return;
         }
-        let new_row = self.rows.get_mut(at.y).unwrap().split(at.x);
-        self.rows.insert(at.y + 1, new_row);
+        self.history.seal();
+        self.record(Edit::Split { at: at.clone() });
+        self.history.seal();
     }
     pub fn delete(&mut self, at: &Position) {
         let len = self.len();
         if at.y >= len {
             return;
         }
-        self.dirty=true;
-        if at.x == self.rows.get_mut(at.y).unwrap().len() && at.y < len - 1 {
-            let next_row = self.rows.remove(at.y + 1);
-            let row = self.rows.get_mut(at.y).unwrap();
-            row.append(&next_row);
+        let Some(row) = self.row(at.y) else {
+            return;
+        };
+        if at.x == row.len() && at.y < len - 1 {
+            self.history.seal();
+            self.record(Edit::Join { at: at.clone() });
+            self.history.seal();
+        } else if at.x < row.len() {
+            let text = row.substring(at.x, at.x + 1);
+            self.record(Edit::Delete {
+                at: at.clone(),
+                text,
+            });
+        }
+    }
+    // 删除 [start, end) 区间的文本，可跨行，作为一步记录
+    pub fn delete_range(&mut self, start: &Position, end: &Position) {
+        let end = if end.y >= self.len() {
+            let Some(y) = self.len().checked_sub(1) else {
+                return;
+            };
+            Position {
+                x: self.row(y).map_or(0, |row| row.len()),
+                y,
+            }
         } else {
-            let row = self.rows.get_mut(at.y).unwrap();
-            row.delete(at.x);
+            end.clone()
+        };
+        if (start.y, start.x) >= (end.y, end.x) {
+            return;
+        }
+        let Some(row) = self.row(start.y) else {
+            return;
+        };
+        self.history.seal();
+        let tail_end = if start.y == end.y { end.x } else { row.len() };
+        if start.x < tail_end {
+            self.record_grouped(Edit::Delete {
+                at: start.clone(),
+                text: row.substring(start.x, tail_end),
+            });
         }
+        for y in start.y..end.y {
+            // 把下一行拼接上来，再删掉其中被选中的部分
+            self.record_grouped(Edit::Join { at: start.clone() });
+            let Some(row) = self.row(start.y) else {
+                break;
+            };
+            let remove_end = if y + 1 == end.y {
+                start.x + end.x
+            } else {
+                row.len()
+            };
+            if start.x < remove_end {
+                self.record_grouped(Edit::Delete {
+                    at: start.clone(),
+                    text: row.substring(start.x, remove_end),
+                });
+            }
+        }
+        self.history.seal();
     }
-    // 保存文件
-    pub fn save(&mut self) -> Result<(), std::io::Error> {
-        if let Some(file_name) = &self.file_name {
-            let mut file = fs::File::create(file_name)?;
-            for row in &self.rows {
-                file.write_all(row.as_bytes())?;
-                file.write_all(b"\n")?;
+    // 应用编辑并记入历史
+    fn record(&mut self, edit: Edit) {
+        self.apply(&edit);
+        self.history.push(edit);
+    }
+    // 应用编辑并追加到当前一步，不与之前的输入合并
+    fn record_grouped(&mut self, edit: Edit) {
+        self.apply(&edit);
+        self.history.extend(edit);
+    }
+    fn apply(&mut self, edit: &Edit) {
+        match edit {
+            Edit::Insert { at, text } => {
+                self.splice(self.char_index(at), 0, text);
+                self.update_states(at.y, at.y);
+            }
+            Edit::Delete { at, text } => {
+                self.splice(self.char_index(at), text.chars().count(), "");
+                self.update_states(at.y, at.y);
+            }
+            Edit::Split { at } => {
+                self.splice(self.char_index(at), 0, "\n");
+                if at.y < self.states.len() {
+                    self.states.insert(at.y + 1, State::Normal);
+                }
+                self.update_states(at.y, at.y + 1);
+            }
+            // 最后一行的 Join 删除其换行符，用于撤销 append_line
+            Edit::Join { at } => {
+                if at.y < self.len() {
+                    self.splice(self.text.line_to_char(at.y + 1) - 1, 1, "");
+                    if at.y + 1 < self.states.len() {
+                        self.states.remove(at.y + 1);
+                    }
+                    self.update_states(at.y, at.y);
+                }
             }
-            self.dirty=false;
         }
-        Ok(())
+    }
+    // 用 inserted 替换从 index 开始的 removed 个字符，并同步语法树
+    fn splice(&mut self, index: usize, removed: usize, inserted: &str) {
+        let start_byte = self.text.char_to_byte(index);
+        let old_end_byte = self.text.char_to_byte(index + removed);
+        let old_points = self.syntax.is_some().then(|| {
+            (
+                syntax_tree::point(&self.text, start_byte),
+                syntax_tree::point(&self.text, old_end_byte),
+            )
+        });
+        // 行数不变时只有所在的行失效，否则其后的行号都变了
+        let line = self.text.char_to_line(index);
+        if inserted.contains('\n') || self.text.slice(index..index + removed).chars().any(|c| c == '\n') {
+            self.rows.get_mut().retain(|y, _| *y < line);
+        } else {
+            self.rows.get_mut().remove(&line);
+        }
+        self.text.remove(index..index + removed);
+        self.text.insert(index, inserted);
+        self.version = self.version.wrapping_add(1);
+        if let (Some(syntax), Some((start_position, old_end_position))) = (&mut self.syntax, old_points) {
+            let new_end_byte = start_byte + inserted.len();
+            syntax.edit(&InputEdit {
+                start_byte,
+                old_end_byte,
+                new_end_byte,
+                start_position,
+                old_end_position,
+                new_end_position: syntax_tree::point(&self.text, new_end_byte),
+            });
+        }
+    }
+    // 撤销一步，返回光标应恢复的位置
+    pub fn undo(&mut self) -> Option<Position> {
+        let edits = self.history.undo()?;
+        for edit in &edits {
+            self.apply(edit);
+        }
+        edits.last().map(Edit::start)
+    }
+    // 重做一步，返回光标应移动到的位置
+    pub fn redo(&mut self) -> Option<Position> {
+        let edits = self.history.redo()?;
+        for edit in &edits {
+            self.apply(edit);
+        }
+        edits.last().map(Edit::end)
+    }
+    // 位置转换为字节下标
+    fn byte_index(&self, at: &Position) -> usize {
+        let byte = self.row(at.y).map_or(0, |row| row.byte_index(at.x));
+        self.text.line_to_byte(at.y.min(self.len())) + byte
+    }
+    fn position_of_byte(&self, byte: usize) -> Position {
+        let y = self.text.byte_to_line(byte);
+        let x = self
+            .row(y)
+            .map_or(0, |row| row.grapheme_index(byte - self.text.line_to_byte(y)));
+        Position { x, y }
+    }
+    // 严格包含 [start, end) 的最小语法节点的范围，用于扩大选区
+    pub fn enclosing_node(&mut self, start: &Position, end: &Position) -> Option<(Position, Position)> {
+        let start = self.byte_index(start);
+        let end = self.byte_index(end);
+        let range = self.syntax.as_mut()?.enclosing(&self.text, start, end)?;
+        Some((self.position_of_byte(range.start), self.position_of_byte(range.end)))
+    }
+    // 光标所在函数的开头
+    pub fn parent_function(&mut self, at: &Position) -> Option<Position> {
+        let byte = self.byte_index(at);
+        let start = self.syntax.as_mut()?.parent_function(&self.text, byte)?;
+        Some(self.position_of_byte(start))
+    }
+    // 光标移动等操作后不再合并输入
+    pub fn seal_history(&mut self) {
+        self.history.seal();
+    }
+    // 保存文件
+    // 先写入同目录的临时文件再原子地改名覆盖原文件，backup 为真时保留 file~ 备份
+    pub fn save(&mut self, backup: bool) -> Result<(), std::io::Error> {
+        if self.read_only {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("File contains bytes that are not valid {}", self.encoding),
+            ));
+        }
+        let Some(file_name) = self.file_name.clone() else {
+            return Ok(());
+        };
+        let mut written_hash = 0;
+        write_atomic(
+            Path::new(&file_name),
+            |writer| {
+                let mut writer = HashWriter {
+                    writer,
+                    hasher: DefaultHasher::new(),
+                };
+                match &self.bytes {
+                    Some(bytes) => writer.write_all(bytes)?,
+                    None => self.write_to(&mut writer)?,
+                }
+                written_hash = writer.hasher.finish();
+                Ok(())
+            },
+            backup,
+            None,
+        )?;
+        self.disk = fs::metadata(&file_name)
+            .ok()
+            .map(|metadata| DiskState::new(&metadata, written_hash));
+        self.ignored_disk = None;
+        if self.is_hex() {
+            self.bytes_modified = false;
+        } else {
+            self.saved_format = (self.line_ending, self.missing_newline);
+            self.mixed_line_endings = false;
+            self.file_type = FileType::from(&file_name);
+            self.states.clear();
+            self.syntax = SyntaxTree::new(&self.file_type.to_string());
+            self.history.mark_saved();
+        }
+        self.remove_swap()
     }
     pub fn is_dirty(&self)->bool{
-        self.dirty
+        !self.history.is_saved()
+            || self.saved_format != (self.line_ending, self.missing_newline)
+            || self.bytes_modified
+    }
+    // 按文件的换行符与编码写出全文，末尾没有换行时去掉最后一个 \n
+    fn write_to(&self, writer: &mut dyn Write) -> Result<(), io::Error> {
+        let mut encoder = self.encoding.encoder(writer)?;
+        let len = self.text.len_bytes();
+        let end = if self.missing_newline { len.saturating_sub(1) } else { len };
+        for chunk in self.text.byte_slice(..end).chunks() {
+            if self.line_ending == LineEnding::Lf {
+                encoder.write_str(chunk)?;
+                continue;
+            }
+            for (index, part) in chunk.split('\n').enumerate() {
+                if index > 0 {
+                    encoder.write_str(self.line_ending.as_str())?;
+                }
+                encoder.write_str(part)?;
+            }
+        }
+        encoder.finish()
+    }
+    pub fn encoding(&self) -> FileEncoding {
+        self.encoding
+    }
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+    pub fn is_hex(&self) -> bool {
+        self.bytes.is_some()
+    }
+    // 十六进制模式下第 y 行的字节数
+    pub fn hex_count(&self, y: usize) -> usize {
+        self.bytes.as_ref().map_or(0, |bytes| {
+            bytes
+                .len()
+                .saturating_sub(y.saturating_mul(hex::BYTES_PER_LINE))
+                .min(hex::BYTES_PER_LINE)
+        })
+    }
+    // 把 at 处的半字节改写为 value 并重新生成该行，不在半字节上时返回 false
+    pub fn set_nibble(&mut self, at: &Position, value: u8) -> bool {
+        let Some(nibble) = hex::nibble_at(at.x, self.hex_count(at.y))
+            .filter(|nibble| hex::nibble_column(*nibble) == at.x)
+        else {
+            return false;
+        };
+        let Some(bytes) = &mut self.bytes else {
+            return false;
+        };
+        let byte = &mut bytes[at.y * hex::BYTES_PER_LINE + nibble / 2];
+        *byte = if nibble % 2 == 0 {
+            (*byte & 0x0f) | (value << 4)
+        } else {
+            (*byte & 0xf0) | value
+        };
+        let line = hex::line(bytes, at.y);
+        let start = self.text.line_to_char(at.y);
+        let removed = self.text.line(at.y).len_chars() - 1;
+        self.splice(start, removed, &line);
+        self.bytes_modified = true;
+        true
+    }
+    pub fn line_ending(&self) -> LineEnding {
+        self.line_ending
+    }
+    pub fn has_mixed_line_endings(&self) -> bool {
+        self.mixed_line_endings
+    }
+    pub fn set_line_ending(&mut self, line_ending: LineEnding) {
+        self.line_ending = line_ending;
+        self.version = self.version.wrapping_add(1);
+    }
+    pub fn has_final_newline(&self) -> bool {
+        !self.missing_newline
+    }
+    pub fn set_final_newline(&mut self, final_newline: bool) {
+        self.missing_newline = !final_newline;
+        self.version = self.version.wrapping_add(1);
+    }
+    // 把未保存的内容写入交换文件的任务，只复制一份 rope，写入交给后台线程
+    // 文档已干净时返回删除之前写的交换文件的任务，没有要做的事时返回 None
+    // 十六进制与只读文档不写交换文件；交换文件与原文件同目录，没有文件名的缓冲区
+    // 无处可写也无从恢复，因此同样不写
+    pub fn swap_job(&mut self) -> Option<swap::Job> {
+        let file_name = self.file_name.clone()?;
+        if self.is_hex() || self.read_only || !self.is_dirty() {
+            self.swap_version.take()?;
+            return Some(Box::new(move || swap::remove(&file_name)));
+        }
+        if self.swap_version == Some(self.version) {
+            return None;
+        }
+        self.swap_version = Some(self.version);
+        let text = self.text.clone();
+        Some(Box::new(move || {
+            write_atomic(
+                &swap::path(&file_name),
+                |writer| {
+                    writer.write_all(swap::header().as_bytes())?;
+                    for chunk in text.chunks() {
+                        writer.write_all(chunk.as_bytes())?;
+                    }
+                    Ok(())
+                },
+                false,
+                // 交换文件与原文件的权限一致，避免泄露内容
+                Some(Path::new(&file_name)),
+            )
+        }))
+    }
+    // 删除本文档写的交换文件，打开前就存在的不动
+    pub fn remove_swap(&mut self) -> Result<(), io::Error> {
+        match (&self.file_name, self.swap_version.take()) {
+            (Some(file_name), Some(_)) => swap::remove(file_name),
+            _ => Ok(()),
+        }
+    }
+    // 用交换文件中的内容替换全文，可以撤销
+    pub fn recover(&mut self, text: &str) {
+        let end = Position {
+            x: 0,
+            y: self.len(),
+        };
+        self.delete_range(&Position::default(), &end);
+        self.insert_str(&Position::default(), text.strip_suffix('\n').unwrap_or(text));
+    }
+    // 从 at 开始沿指定方向查找 query
+    pub fn find(&self, query: &str, at: &Position, direction: SearchDirection) -> Option<Position> {
+        if at.y >= self.len() {
+            return None;
+        }
+        let mut position = at.clone();
+        let (start, end) = match direction {
+            SearchDirection::Forward => (at.y, self.len()),
+            SearchDirection::Backward => (0, at.y.saturating_add(1)),
+        };
+        for _ in start..end {
+            let row = self.row(position.y)?;
+            if let Some(x) = row.find(query, position.x, direction) {
+                position.x = x;
+                return Some(position);
+            }
+            match direction {
+                SearchDirection::Forward => {
+                    position.y = position.y.saturating_add(1);
+                    position.x = 0;
+                }
+                SearchDirection::Backward => {
+                    position.y = position.y.saturating_sub(1);
+                    position.x = self.row(position.y).map_or(0, |row| row.len());
+                }
+            }
+        }
+        None
+    }
+    // 从 at 开始向后查找第一个正则匹配，返回位置与字位长度
+    pub fn find_regex(&self, re: &Regex, at: &Position) -> Option<(Position, usize)> {
+        let mut x = at.x;
+        for y in at.y..self.len() {
+            if let Some((start, end)) = self.row(y)?.find_regex(re, x) {
+                return Some((Position { x: start, y }, end - start));
+            }
+            x = 0;
+        }
+        None
+    }
+    // 替换 at 处的正则匹配，返回替换文本的字位长度
+    pub fn replace_regex(&mut self, re: &Regex, at: &Position, replacement: &str) -> Option<usize> {
+        let row = self.row(at.y)?;
+        let (start, end) = row.find_regex(re, at.x)?;
+        let text = row.expand_regex(re, at.x, replacement)?;
+        let start = Position { x: start, y: at.y };
+        let removed = row.substring(start.x, end);
+        self.history.seal();
+        for edit in [
+            Edit::Delete {
+                at: start.clone(),
+                text: removed,
+            },
+            Edit::Insert {
+                at: start,
+                text: text.clone(),
+            },
+        ] {
+            self.record_grouped(edit);
+        }
+        self.history.seal();
+        Some(text.graphemes(true).count())
+    }
+}
+
+// 读取文件内容并记下其状态
+fn read_file(filename: &str) -> Result<(Vec<u8>, DiskState), io::Error> {
+    let bytes = fs::read(filename)?;
+    let metadata = fs::metadata(filename)?;
+    let disk = DiskState::new(&metadata, hash(&bytes));
+    Ok((bytes, disk))
+}
+
+// 给错误附上说明，保留原来的错误种类
+fn context(error: io::Error, message: String) -> io::Error {
+    io::Error::new(error.kind(), format!("{message}: {error}"))
+}
+
+// 在 directory 中新建一个不存在的临时文件
+fn create_temp(directory: &Path, file_name: &str) -> Result<(PathBuf, fs::File), io::Error> {
+    let mut attempt = 0;
+    loop {
+        let path = directory.join(format!(".{file_name}.{}.{attempt}.tmp", process::id()));
+        match OpenOptions::new().write(true).create_new(true).open(&path) {
+            Ok(file) => return Ok((path, file)),
+            Err(error) if error.kind() == io::ErrorKind::AlreadyExists && attempt < 100 => {
+                attempt += 1;
+            }
+            Err(error) => {
+                return Err(context(
+                    error,
+                    format!("Could not create temp file in {}", directory.display()),
+                ))
+            }
+        }
+    }
+}
+
+// 写入临时文件并落盘，写入内容前先设好原文件的权限
+fn write_temp(
+    file: fs::File,
+    temp: &Path,
+    write: impl FnOnce(&mut dyn Write) -> Result<(), io::Error>,
+    permissions: Option<fs::Permissions>,
+) -> Result<(), io::Error> {
+    let describe = |error, action: &str| context(error, format!("Could not {action} {}", temp.display()));
+    if let Some(permissions) = permissions {
+        file.set_permissions(permissions)
+            .map_err(|error| describe(error, "set permissions on"))?;
+    }
+    let mut writer = BufWriter::new(file);
+    write(&mut writer).map_err(|error| describe(error, "write"))?;
+    let file = writer
+        .into_inner()
+        .map_err(|error| describe(error.into_error(), "write"))?;
+    file.sync_all().map_err(|error| describe(error, "sync"))
+}
+
+// 原子地写入 path，新文件沿用 permissions_of 的权限，默认为 path 原来的权限
+fn write_atomic(
+    path: &Path,
+    write: impl FnOnce(&mut dyn Write) -> Result<(), io::Error>,
+    backup: bool,
+    permissions_of: Option<&Path>,
+) -> Result<(), io::Error> {
+    // 符号链接写到其指向的文件
+    let path = match fs::canonicalize(path) {
+        Ok(target) => target,
+        Err(error) if error.kind() == io::ErrorKind::NotFound => path.to_path_buf(),
+        Err(error) => return Err(context(error, format!("Could not resolve {}", path.display()))),
+    };
+    let existing = match fs::metadata(&path) {
+        Ok(metadata) => Some(metadata.permissions()),
+        Err(error) if error.kind() == io::ErrorKind::NotFound => None,
+        Err(error) => return Err(context(error, format!("Could not read {}", path.display()))),
+    };
+    let permissions = permissions_of
+        .and_then(|source| fs::metadata(source).ok())
+        .map(|metadata| metadata.permissions())
+        .or_else(|| existing.clone());
+    let directory = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent.to_path_buf(),
+        _ => PathBuf::from("."),
+    };
+    let file_name = path
+        .file_name()
+        .and_then(|name| name.to_str())
+        .unwrap_or("untitled");
+    let (temp, file) = create_temp(&directory, file_name)?;
+    let result = write_temp(file, &temp, write, permissions.clone()).and_then(|()| {
+        if backup && existing.is_some() {
+            let backup_path = directory.join(format!("{file_name}~"));
+            fs::copy(&path, &backup_path).map_err(|error| {
+                context(error, format!("Could not write backup {}", backup_path.display()))
+            })?;
+        }
+        fs::rename(&temp, &path).map_err(|error| {
+            context(
+                error,
+                format!("Could not rename {} to {}", temp.display(), path.display()),
+            )
+        })
+    });
+    if result.is_err() {
+        let _ = fs::remove_file(&temp);
+        return result;
+    }
+    // 目录项也落盘，有的文件系统不支持，忽略错误
+    if let Ok(directory) = fs::File::open(&directory) {
+        let _ = directory.sync_all();
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::highlighting::Type;
+
+    fn line(document: &Document, y: usize) -> String {
+        document.row(y).map_or_else(String::new, |row| row.substring(0, row.len()))
+    }
+
+    fn first_type(document: &mut Document, y: usize) -> Type {
+        document.highlighted_row(y).map_or(Type::None, |row| row.highlighting(0))
+    }
+
+    #[test]
+    fn opening_a_block_comment_highlights_the_rows_below() {
+        let mut document = Document::from_bytes("test.c", b"int a;\nint b;\nint c;\n", None);
+        for y in 0..document.len() {
+            document.highlighted_row(y);
+        }
+        document.insert(&Position { x: 0, y: 1 }, '/');
+        document.insert(&Position { x: 1, y: 1 }, '*');
+        assert!(first_type(&mut document, 0) == Type::Builtin);
+        assert!(first_type(&mut document, 1) == Type::Comment);
+        assert!(first_type(&mut document, 2) == Type::Comment);
+        document.delete(&Position { x: 1, y: 1 });
+        assert!(first_type(&mut document, 1) == Type::Operator);
+        assert!(first_type(&mut document, 2) == Type::Builtin);
+        document.delete(&Position { x: 0, y: 1 });
+        assert!(first_type(&mut document, 1) == Type::Builtin);
+    }
+
+    #[test]
+    fn state_update_stops_once_a_row_ends_as_before() {
+        let mut document = Document::from_bytes("test.c", b"int a;\nint b;\nint c;\nint d;\n", None);
+        for y in 0..document.len() {
+            document.highlighted_row(y);
+        }
+        // 用不可能出现的状态做标记，看更新有没有走到这一行
+        document.states[3] = State::Tag;
+        document.insert(&Position { x: 0, y: 1 }, 'x');
+        assert!(document.states[1] == State::Normal);
+        assert!(document.states[3] == State::Tag);
+        document.insert(&Position { x: 0, y: 0 }, '/');
+        document.insert(&Position { x: 1, y: 0 }, '*');
+        assert!(document.states.iter().all(|state| *state == State::Comment("*/")));
+    }
+
+    #[test]
+    fn replace_regex_expands_groups() {
+        let mut document = Document::from_text("foo=1, bar=2");
+        let re = Regex::new(r"(\w+)=(\d)").unwrap();
+        assert_eq!(document.replace_regex(&re, &Position::default(), "$2=$1"), Some(5));
+        assert_eq!(line(&document, 0), "1=foo, bar=2");
+        document.undo();
+        assert_eq!(line(&document, 0), "foo=1, bar=2");
+    }
+
+    #[test]
+    fn skipped_match_is_left_alone() {
+        let mut document = Document::from_text("foo=1, bar=2");
+        let re = Regex::new(r"(\w+)=(\d)").unwrap();
+        let (position, len) = document.find_regex(&re, &Position::default()).unwrap();
+        assert!(position == Position::default());
+        // 回答 n 时从匹配之后继续
+        let at = Position { x: position.x + len, y: 0 };
+        let (position, _) = document.find_regex(&re, &at).unwrap();
+        assert!(position == Position { x: 7, y: 0 });
+        assert_eq!(document.replace_regex(&re, &position, "$2=$1"), Some(5));
+        assert_eq!(line(&document, 0), "foo=1, 2=bar");
+    }
+
+    #[test]
+    fn replacement_containing_the_pattern_is_not_matched_again() {
+        let mut document = Document::from_text("a a\na");
+        let re = Regex::new("a").unwrap();
+        let mut at = Position::default();
+        let mut replaced = 0;
+        while let Some((position, _)) = document.find_regex(&re, &at) {
+            let inserted = document.replace_regex(&re, &position, "aa").unwrap();
+            at = Position { x: position.x + inserted, y: position.y };
+            replaced += 1;
+        }
+        assert_eq!(replaced, 3);
+        assert_eq!(document.text(), "aa aa\naa\n");
+    }
+
+    #[test]
+    fn cached_rows_follow_edits() {
+        let mut document = Document::from_text("one\ntwo\nthree");
+        assert_eq!(line(&document, 1), "two");
+        document.insert(&Position { x: 3, y: 1 }, 's');
+        assert_eq!(line(&document, 1), "twos");
+        document.insert(&Position { x: 1, y: 0 }, '\n');
+        assert_eq!(line(&document, 0), "o");
+        assert_eq!(line(&document, 1), "ne");
+        assert_eq!(line(&document, 2), "twos");
+        assert_eq!(line(&document, 3), "three");
+        document.delete(&Position { x: 1, y: 0 });
+        assert_eq!(line(&document, 0), "one");
+        assert_eq!(line(&document, 1), "twos");
+        assert_eq!(line(&document, 2), "three");
+        assert!(document.row(3).is_none());
+    }
+
+    #[test]
+    fn undo_typing_in_empty_document_leaves_it_empty() {
+        let mut document = Document::default();
+        document.insert(&Position::default(), 'a');
+        assert_eq!(document.text(), "a\n");
+        assert!(document.is_dirty());
+        document.undo();
+        assert_eq!(document.text(), "");
+        assert_eq!(document.len(), 0);
+        assert!(!document.is_dirty());
+        document.redo();
+        assert_eq!(document.text(), "a\n");
+    }
+
+    #[test]
+    fn undo_appended_line_removes_it() {
+        let mut document = Document::from_text("a");
+        document.insert(&Position { x: 0, y: 1 }, 'b');
+        assert_eq!(document.text(), "a\nb\n");
+        document.undo();
+        assert_eq!(document.text(), "a\n");
+        assert!(!document.is_dirty());
+    }
+
+    #[test]
+    fn undo_pasted_text_in_empty_document() {
+        let mut document = Document::default();
+        document.insert_str(&Position::default(), "one\ntwo");
+        assert_eq!(document.text(), "one\ntwo\n");
+        document.undo();
+        assert_eq!(document.text(), "");
+        assert!(!document.is_dirty());
+    }
+
+    #[test]
+    fn syntax_captures_spanning_rows_are_clipped_to_the_row() {
+        let mut document = Document::from_bytes("main.rs", b"/* one\ntwo */ fn x() {}\n", None);
+        let row = document.highlighted_row(1).unwrap();
+        assert!(row.highlighting(0) == Type::Comment);
+        assert!(row.highlighting(5) == Type::Comment);
+        assert!(row.highlighting(7) == Type::Keyword);
+    }
+
+    // 按文件内容打开后原样写回
+    fn round_trip(bytes: &[u8]) -> (Document, Vec<u8>) {
+        let document = Document::from_bytes("file.txt", bytes, None);
+        let mut written = Vec::new();
+        document.write_to(&mut written).unwrap();
+        (document, written)
+    }
+
+    #[test]
+    fn detect_line_ending_by_majority() {
+        assert!(LineEnding::detect("") == LineEnding::Lf);
+        assert!(LineEnding::detect("a\nb\n") == LineEnding::Lf);
+        assert!(LineEnding::detect("a\r\nb\r\n") == LineEnding::CrLf);
+        assert!(LineEnding::detect("a\rb\r") == LineEnding::Cr);
+        assert!(LineEnding::detect("a\r\nb\r\nc\n") == LineEnding::CrLf);
+        assert!(LineEnding::detect("a\nb\nc\r\n") == LineEnding::Lf);
+        assert!(LineEnding::detect("a\rb\rc\r\n") == LineEnding::Cr);
+    }
+
+    #[test]
+    fn pure_line_endings_round_trip() {
+        for bytes in [
+            &b"a\nb\n"[..],
+            b"a\nb",
+            b"a\r\nb\r\n",
+            b"a\r\nb",
+            b"a\rb\r",
+            b"a\rb",
+            b"\n\n",
+            b"\r\n\r\n",
+            b"\r\r",
+            b"",
+        ] {
+            let (document, written) = round_trip(bytes);
+            assert_eq!(written, bytes);
+            assert!(!document.has_mixed_line_endings());
+        }
+        let (document, _) = round_trip(b"a\rb\rc\r");
+        assert_eq!(document.len(), 3);
+    }
+
+    #[test]
+    fn stray_carriage_returns_stay_in_the_row() {
+        for bytes in [&b"a\r\nb\nc\rd\n"[..], b"a\r\r\nb\r\n", b"a\r\nb\r"] {
+            let (document, written) = round_trip(bytes);
+            assert_eq!(written, bytes);
+            assert!(!document.has_mixed_line_endings());
+        }
+    }
+
+    #[test]
+    fn mixed_line_endings_are_flagged_and_normalized() {
+        let (document, written) = round_trip(b"a\r\nb\nc\r\n");
+        assert!(document.has_mixed_line_endings());
+        assert_eq!(document.text(), "a\nb\nc\n");
+        assert_eq!(written, b"a\r\nb\r\nc\r\n");
+
+        let (document, written) = round_trip(b"a\rb\r\nc\nd\r");
+        assert!(document.has_mixed_line_endings());
+        assert_eq!(document.text(), "a\nb\nc\nd\n");
+        assert_eq!(written, b"a\rb\rc\rd\r");
+    }
+
+    // 每个测试使用独立的临时目录
+    fn temp_dir(name: &str) -> PathBuf {
+        let directory = std::env::temp_dir().join(format!("mini-editor-{}-{name}", process::id()));
+        let _ = fs::remove_dir_all(&directory);
+        fs::create_dir_all(&directory).unwrap();
+        directory
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn swap_file_gets_permissions_of_the_original() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let directory = temp_dir("swap-permissions");
+        let path = directory.join("secret.txt");
+        fs::write(&path, "secret\n").unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o600)).unwrap();
+        let file_name = path.to_str().unwrap();
+        let mut document = Document::open(file_name).unwrap();
+        document.insert(&Position::default(), 'x');
+        let job = document.swap_job().unwrap();
+        // 之后的修改不影响已经取出的内容
+        document.insert(&Position::default(), 'y');
+        job().unwrap();
+        let metadata = fs::metadata(swap::path(file_name)).unwrap();
+        assert_eq!(metadata.permissions().mode() & 0o777, 0o600);
+        assert_eq!(swap::read(file_name).unwrap().text, "xsecret\n");
+        document.remove_swap().unwrap();
+        assert!(!swap::path(file_name).exists());
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn delete_range_within_and_across_lines() {
+        let mut document = Document::from_text("hello world\nsecond line\nthird");
+        document.delete_range(&Position { x: 5, y: 0 }, &Position { x: 11, y: 0 });
+        assert_eq!(document.text(), "hello\nsecond line\nthird\n");
+        document.delete_range(&Position { x: 3, y: 0 }, &Position { x: 7, y: 1 });
+        assert_eq!(document.text(), "helline\nthird\n");
+        // 一次撤销恢复整个区间
+        document.undo();
+        assert_eq!(document.text(), "hello\nsecond line\nthird\n");
+    }
+
+    #[test]
+    fn delete_range_past_the_end_stops_at_the_last_line() {
+        let mut document = Document::from_text("one\ntwo\nthree");
+        document.delete_range(&Position { x: 1, y: 1 }, &Position { x: 0, y: 3 });
+        assert_eq!(document.text(), "one\nt\n");
+        document.undo();
+        assert_eq!(document.text(), "one\ntwo\nthree\n");
+    }
+
+    #[test]
+    fn text_range_spans_lines() {
+        let document = Document::from_text("one\ntwo\nthree");
+        assert_eq!(document.text_range(&Position { x: 1, y: 0 }, &Position { x: 2, y: 2 }), "ne\ntwo\nth");
+        assert_eq!(document.text_range(&Position { x: 2, y: 1 }, &Position { x: 1, y: 1 }), "");
+    }
+
+    fn write_text(path: &Path, text: &str, backup: bool) -> Result<(), io::Error> {
+        write_atomic(path, |writer| writer.write_all(text.as_bytes()), backup, None)
+    }
+
+    #[test]
+    fn write_atomic_creates_and_replaces_files() {
+        let directory = temp_dir("write-atomic");
+        let path = directory.join("file.txt");
+        write_text(&path, "one", false).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "one");
+        write_text(&path, "two", false).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "two");
+        assert_eq!(fs::read_dir(&directory).unwrap().count(), 1);
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn write_atomic_keeps_backup_of_the_previous_contents() {
+        let directory = temp_dir("write-backup");
+        let path = directory.join("file.txt");
+        // 新文件没有可备份的内容
+        write_text(&path, "one", true).unwrap();
+        assert!(!directory.join("file.txt~").exists());
+        write_text(&path, "two", true).unwrap();
+        assert_eq!(fs::read_to_string(directory.join("file.txt~")).unwrap(), "one");
+        assert_eq!(fs::read_to_string(&path).unwrap(), "two");
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn failed_write_leaves_the_original_untouched() {
+        let directory = temp_dir("write-failure");
+        let path = directory.join("file.txt");
+        write_text(&path, "original", false).unwrap();
+        let result = write_atomic(
+            &path,
+            |writer| {
+                writer.write_all(b"partial")?;
+                Err(io::Error::other("disk full"))
+            },
+            false,
+            None,
+        );
+        assert!(result.is_err());
+        assert_eq!(fs::read_to_string(&path).unwrap(), "original");
+        // 临时文件已清理
+        assert_eq!(fs::read_dir(&directory).unwrap().count(), 1);
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn write_atomic_keeps_permissions_and_follows_symlinks() {
+        use std::os::unix::fs::{symlink, PermissionsExt};
+
+        let directory = temp_dir("write-permissions");
+        let target = directory.join("target.sh");
+        write_text(&target, "old", false).unwrap();
+        fs::set_permissions(&target, fs::Permissions::from_mode(0o750)).unwrap();
+        let link = directory.join("link.sh");
+        symlink(&target, &link).unwrap();
+        write_text(&link, "new", false).unwrap();
+        assert!(fs::symlink_metadata(&link).unwrap().file_type().is_symlink());
+        assert_eq!(fs::read_to_string(&target).unwrap(), "new");
+        assert_eq!(fs::metadata(&target).unwrap().permissions().mode() & 0o777, 0o750);
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn save_writes_back_and_marks_clean() {
+        let directory = temp_dir("save");
+        let path = directory.join("file.txt");
+        fs::write(&path, "a\r\nb").unwrap();
+        let mut document = Document::open(path.to_str().unwrap()).unwrap();
+        document.insert(&Position::default(), 'x');
+        assert!(document.is_dirty());
+        document.save(true).unwrap();
+        assert!(!document.is_dirty());
+        assert_eq!(fs::read(&path).unwrap(), b"xa\r\nb");
+        assert_eq!(fs::read(directory.join("file.txt~")).unwrap(), b"a\r\nb");
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn set_nibble_overwrites_bytes_in_place() {
+        let mut document = Document::from_hex("data.bin", vec![0x00, 0x11, 0x22]);
+        let column = |nibble| hex::nibble_column(nibble);
+        assert!(document.set_nibble(&Position { x: column(0), y: 0 }, 0xa));
+        assert!(document.set_nibble(&Position { x: column(5), y: 0 }, 0xf));
+        // 不在半字节上的列不改写
+        assert!(!document.set_nibble(&Position { x: column(1) + 1, y: 0 }, 0x1));
+        assert!(!document.set_nibble(&Position { x: 0, y: 0 }, 0x1));
+        assert_eq!(document.bytes.as_deref(), Some(&[0xa0, 0x11, 0x2f][..]));
+        assert_eq!(line(&document, 0), hex::line(&[0xa0, 0x11, 0x2f], 0));
+        assert!(document.is_dirty());
     }
 }
@@ -1,43 +1,120 @@
 use std::{
+    cmp::Ordering,
     env,
+    mem,
+    ops::Range,
+    fs,
+    path::{Path, PathBuf},
+    sync::mpsc::{self, Receiver},
     time::{Duration, Instant},
 };
 
 use crossterm::{
-    event::{KeyCode, KeyModifiers},
-    style::Color,
+    event::{KeyCode, KeyModifiers, MouseButton, MouseEventKind},
     terminal::disable_raw_mode,
 };
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+use regex::Regex;
 
-use crate::{document::Document, row::Row, terminal::Terminal};
-const VERSION: &str = env!("CARGO_PKG_VERSION");
-const STATUS_BG_COLOR: Color = Color::Rgb {
-    r: 239,
-    g: 239,
-    b: 239,
-};
-const STATUS_FG_COLOR: Color = Color::Rgb {
-    r: 63,
-    g: 63,
-    b: 63,
+use crate::{
+    config::{Command, Config, LineNumbers},
+    document::{DiskChange, Document, LineEnding},
+    encoding::FileEncoding,
+    finder,
+    hex,
+    highlighting,
+    layout::{Layout, Rect, SplitDirection},
+    swap,
+    terminal::{Event, Terminal},
 };
-const QUIT_TIMES: u8 = 3;
+const VERSION: &str = env!("CARGO_PKG_VERSION");
+// 双击的最大间隔
+const DOUBLE_CLICK_TIME: Duration = Duration::from_millis(400);
+// 滚轮每格滚动的行数
+const WHEEL_LINES: usize = 3;
+// 调整窗口大小的步长，单位为百分比
+const RESIZE_STEP: isize = 10;
+// 提示栏上方列表最多显示的条目数
+const OVERLAY_ITEMS: usize = 10;
 pub struct Editor {
     should_quit: bool,
     terminal: Terminal,
     cursor_position: Position,
     document: Document,
+    // 折行模式下 x 为 y 行内第一个可见的视觉行
     offset: Position,
     status_message: StatusMessage,
     quit_times: u8,
+    highlighted_match: Option<(Position, usize)>,
+    selection_anchor: Option<Position>,
+    clipboard: Option<Clipboard>,
+    line_numbers: LineNumbers,
+    tab_width: usize,
+    soft_wrap: bool,
+    // 上一次鼠标单击的时间与位置，用于识别双击
+    last_click: Option<(Instant, Position)>,
+    // 所有打开的缓冲区，当前缓冲区的内容换入上面的 document 等字段
+    buffers: Vec<Buffer>,
+    current_buffer: usize,
+    // 所有窗口，当前窗口的光标与偏移换入上面的字段
+    windows: Vec<Window>,
+    current_window: usize,
+    layout: Layout,
+    // 提示时显示在消息栏上方的候选列表及选中项
+    overlay: Vec<String>,
+    overlay_selected: Option<usize>,
+    // 保存时保留 file~ 备份
+    backup: bool,
+    config: Config,
+    // 监视打开文件所在的目录，无法监视时为 None
+    watcher: Option<RecommendedWatcher>,
+    file_events: Receiver<notify::Result<notify::Event>>,
+    // 在后台写交换文件
+    swap_writer: swap::Writer,
+}
+
+// 缓冲区：文档及其光标与滚动位置
+#[derive(Default)]
+struct Buffer {
+    document: Document,
+    cursor_position: Position,
+    offset: Position,
 }
 
+// 窗口：显示某个缓冲区，有自己的光标与滚动位置
 #[derive(Default)]
+struct Window {
+    buffer: usize,
+    cursor_position: Position,
+    offset: Position,
+}
+
+#[derive(Default, Clone, PartialEq, Eq)]
 pub struct Position {
     pub x: usize,
     pub y: usize,
 }
 
+// 搜索方向
+#[derive(PartialEq, Eq, Copy, Clone)]
+pub enum SearchDirection {
+    Forward,
+    Backward,
+}
+
+// 剪贴板内容，整行复制时粘贴到当前行之前
+struct Clipboard {
+    text: String,
+    linewise: bool,
+}
+
+// 行内高亮的种类
+#[derive(PartialEq, Eq, Clone, Copy)]
+enum Highlight {
+    Match,
+    Selection,
+}
+
 // 状态信息
 struct StatusMessage {
     text: String,
@@ -52,8 +129,80 @@ impl StatusMessage {
         }
     }
 }
+impl Default for Editor {
+    fn default() -> Self{
+        // 获取文件名，初始化提示信息
+        let args: Vec<String> = env::args().collect();
+        let (config, errors) = Config::load();
+        let mut initial_status = config.help();
+        if !errors.is_empty() {
+            initial_status = format!("ERR:Config: {}", errors.join("; "));
+        }
+        // 打开命令行给出的所有文件
+        let mut buffers = Vec::new();
+        let mut failed = Vec::new();
+        for file_name in args.iter().skip(1) {
+            match Document::open(file_name) {
+                Ok(document) => buffers.push(Buffer {
+                    document,
+                    ..Buffer::default()
+                }),
+                Err(_) => failed.push(file_name.as_str()),
+            }
+        }
+        if !failed.is_empty() {
+            initial_status = format!("ERR:Could not Open file:{}", failed.join(", "));
+            if !errors.is_empty() {
+                initial_status = format!("{initial_status}; Config: {}", errors.join("; "));
+            }
+        }
+        if buffers.is_empty() {
+            buffers.push(Buffer::default());
+        }
+        let document = mem::take(&mut buffers[0].document);
+        let (sender, file_events) = mpsc::channel();
+        Self {
+            should_quit: false,
+            terminal: Terminal::default().expect("Failed to initialize terminal"),
+            document,
+            cursor_position: Position::default(),
+            offset: Position::default(),
+            status_message: StatusMessage::from(initial_status),
+            quit_times: config.quit_times,
+            highlighted_match: None,
+            selection_anchor: None,
+            clipboard: None,
+            line_numbers: config.line_numbers,
+            tab_width: config.tab_width,
+            soft_wrap: config.soft_wrap,
+            last_click: None,
+            buffers,
+            current_buffer: 0,
+            windows: vec![Window::default()],
+            current_window: 0,
+            layout: Layout::default(),
+            overlay: Vec::new(),
+            overlay_selected: None,
+            backup: config.backup,
+            watcher: notify::recommended_watcher(sender).ok(),
+            file_events,
+            config,
+            swap_writer: swap::Writer::default(),
+        }
+    }
+}
+
 impl Editor {
     pub fn run(&mut self) {
+        // 命令行打开的文件逐个检查交换文件
+        for index in 0..self.buffers.len() {
+            self.switch_buffer(index);
+            self.watch();
+            if let Err(error) = self.check_swap() {
+                self.die(error);
+            }
+        }
+        self.switch_buffer(0);
         loop {
             if let Err(error) = self.refresh_screen() {
                 self.die(error);
@@ -73,15 +222,18 @@ impl Editor {
             self.terminal.clear_screen()?;
             println!("Goodbye.\r");
         } else {
-            self.draw_rows()?;
-            // 状态栏绘制
-            self.draw_status_bar()?;
-            self.draw_message_bar();
-            //光标移动
-            self.terminal.cursor_position(&Position {
-                x: self.cursor_position.x.saturating_sub(self.offset.x),
-                y: self.cursor_position.y.saturating_sub(self.offset.y),
-            })?;
+            self.draw_windows()?;
+            self.draw_overlay()?;
+            self.draw_message_bar()?;
+            //光标移动，滚轮可能已把光标滚出屏幕
+            let area = self.area();
+            match self.cursor_screen_position() {
+                Some(position) => self.terminal.cursor_position(&Position {
+                    x: area.x.saturating_add(position.x),
+                    y: area.y.saturating_add(position.y),
+                })?,
+                None => return self.terminal.flush(),
+            }
         }
         self.terminal.cursor_show()?;
         self.terminal.flush()
@@ -89,113 +241,1186 @@ impl Editor {
 
     fn save(&mut self) {
         if self.document.file_name.is_none() {
-            let new_name = self.prompt("Save as: ").unwrap_or(None);
-            if new_name.is_none() {
+            let new_name = self.prompt("Save as: ", |_, _, _| ()).unwrap_or(None);
+            let Some(new_name) = new_name else {
                 self.status_message = StatusMessage::from("Save aborted.".to_string());
                 return;
+            };
+            // 另存为已有的文件时先确认
+            if Path::new(&new_name).exists() {
+                let prompt = format!("{new_name} already exists. Overwrite? (y/n): ");
+                let answer = self.prompt(&prompt, |_, _, _| ()).unwrap_or(None);
+                if answer.as_deref().map(str::trim) != Some("y") {
+                    self.status_message = StatusMessage::from("Save aborted.".to_string());
+                    return;
+                }
             }
-            self.document.file_name = new_name;
+            self.document.file_name = Some(new_name);
         }
-        if self.document.save().is_ok() {
-            self.status_message = StatusMessage::from("File saved successfully".to_string());
+        // 不悄悄覆盖其他程序写入的内容
+        if self.document.conflicts_with_disk() {
+            let answer = self
+                .prompt("File changed on disk since it was read. Overwrite? (y/n): ", |_, _, _| ())
+                .unwrap_or(None);
+            if answer.as_deref().map(str::trim) != Some("y") {
+                self.status_message = StatusMessage::from("Save aborted.".to_string());
+                return;
+            }
+        }
+        // 保存后会删除交换文件，先等后台写完，免得又被写回来
+        self.swap_writer.flush();
+        self.status_message = StatusMessage::from(match self.document.save(self.backup) {
+            Ok(()) => "File saved successfully".to_string(),
+            Err(error) => format!("Error writing file: {error}"),
+        });
+        // 另存为的文件可能在新的目录
+        self.watch();
+    }
+    // 监视当前文件所在的目录，原子保存会替换文件本身，只监视文件会丢失事件
+    fn watch(&mut self) {
+        let (Some(watcher), Some(file_name)) = (&mut self.watcher, &self.document.file_name) else {
+            return;
+        };
+        let directory = match Path::new(file_name).parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => Path::new("."),
+        };
+        let _ = watcher.watch(directory, RecursiveMode::NonRecursive);
+    }
+    // 收到文件事件后检查各缓冲区的文件，干净的直接重新读取，有修改的询问
+    fn check_files(&mut self) -> Result<(), std::io::Error> {
+        if self.file_events.try_iter().count() == 0 {
+            return Ok(());
+        }
+        let active = self.current_buffer;
+        for index in 0..self.buffers.len() {
+            let Some(change) = self.buffer_document_mut(index).check_disk() else {
+                continue;
+            };
+            let name = self.buffer_name(index);
+            self.switch_buffer(index);
+            let reload = match change {
+                DiskChange::Deleted => {
+                    self.status_message = StatusMessage::from(format!("{name} was deleted on disk"));
+                    false
+                }
+                DiskChange::Modified if !self.document.is_dirty() => true,
+                DiskChange::Modified => {
+                    let prompt =
+                        format!("{name} changed on disk. r = reload and lose your changes | Esc = keep yours: ");
+                    let answer = self.prompt(&prompt, |_, _, _| ())?;
+                    let reload = answer.as_deref().map(str::trim) == Some("r");
+                    if !reload {
+                        self.document.ignore_disk_change();
+                        self.status_message = StatusMessage::from(format!("Kept your version of {name}"));
+                    }
+                    reload
+                }
+            };
+            if reload {
+                self.swap_writer.flush();
+                self.status_message = StatusMessage::from(match self.document.reload() {
+                    Ok(()) => format!("Reloaded {name}, it changed on disk"),
+                    Err(error) => format!("ERR:Could not reload {name}: {error}"),
+                });
+                self.clamp_cursor();
+            }
+            self.switch_buffer(active);
+        }
+        Ok(())
+    }
+
+    // 增量搜索，Esc 取消时恢复光标与偏移
+    fn search(&mut self) {
+        let old_position = self.cursor_position.clone();
+        let old_offset = self.offset.clone();
+        let mut direction = SearchDirection::Forward;
+        let query = self
+            .prompt(
+                "Search (ESC to cancel, Arrows to navigate): ",
+                |editor, key, query| {
+                    let mut moved = false;
+                    match key {
+                        KeyCode::Right | KeyCode::Down => {
+                            direction = SearchDirection::Forward;
+                            editor.move_cursor(KeyCode::Right);
+                            moved = true;
+                        }
+                        KeyCode::Left | KeyCode::Up => direction = SearchDirection::Backward,
+                        _ => direction = SearchDirection::Forward,
+                    }
+                    if let Some(position) =
+                        editor
+                            .document
+                            .find(query, &editor.cursor_position, direction)
+                    {
+                        editor.cursor_position = position;
+                        editor.scroll();
+                    } else if moved {
+                        editor.move_cursor(KeyCode::Left);
+                    }
+                },
+            )
+            .unwrap_or(None);
+        if query.is_none() {
+            self.cursor_position = old_position;
+            self.offset = old_offset;
+            self.scroll();
+        }
+    }
+
+    // 正则替换，逐个匹配确认
+    fn replace(&mut self) -> Result<(), std::io::Error> {
+        let Some(pattern) = self.prompt("Replace regex: ", |_, _, _| ())? else {
+            return Ok(());
+        };
+        let re = match Regex::new(&pattern) {
+            Ok(re) => re,
+            Err(error) => {
+                self.status_message = StatusMessage::from(format!("Invalid regex: {error}"));
+                return Ok(());
+            }
+        };
+        // 空的替换文本表示删除匹配，Esc 则放弃替换
+        let Some(replacement) = self.read_prompt(&format!("Replace /{pattern}/ with: "), |_, _, _| ())? else {
+            return Ok(());
+        };
+        let mut at = Position::default();
+        let mut replace_all = false;
+        let mut replaced = 0;
+        while let Some((position, len)) = self.document.find_regex(&re, &at) {
+            if !replace_all {
+                self.cursor_position = position.clone();
+                self.scroll();
+                self.highlighted_match = Some((position.clone(), len));
+                self.status_message =
+                    StatusMessage::from("Replace this match? (y = yes | n = no | a = all | q = quit)".to_string());
+                self.refresh_screen()?;
+                let answer = loop {
+                    match self.terminal.read_key()? {
+                        Event::Key(code, KeyModifiers::NONE | KeyModifiers::SHIFT) => match code {
+                            KeyCode::Char(c @ ('y' | 'n' | 'a' | 'q')) => break c,
+                            KeyCode::Esc => break 'q',
+                            _ => (),
+                        },
+                        Event::Resize => {
+                            self.resize()?;
+                            self.refresh_screen()?;
+                        }
+                        _ => (),
+                    }
+                };
+                match answer {
+                    'n' => {
+                        at = Position {
+                            x: position.x.saturating_add(len.max(1)),
+                            y: position.y,
+                        };
+                        continue;
+                    }
+                    'a' => replace_all = true,
+                    'q' => break,
+                    _ => (),
+                }
+            }
+            let inserted = self
+                .document
+                .replace_regex(&re, &position, &replacement)
+                .unwrap_or(0);
+            replaced += 1;
+            at = Position {
+                x: position.x.saturating_add(inserted),
+                y: position.y,
+            };
+            self.cursor_position = at.clone();
+        }
+        self.highlighted_match = None;
+        self.status_message = StatusMessage::from(format!("Replaced {replaced} occurrence(s)"));
+        self.scroll();
+        Ok(())
+    }
+
+    // 有序的选区 [start, end)，锚点与光标重合时为空
+    fn selection(&self) -> Option<(Position, Position)> {
+        let anchor = self.selection_anchor.as_ref()?;
+        let cursor = &self.cursor_position;
+        match (anchor.y, anchor.x).cmp(&(cursor.y, cursor.x)) {
+            Ordering::Less => Some((anchor.clone(), cursor.clone())),
+            Ordering::Greater => Some((cursor.clone(), anchor.clone())),
+            Ordering::Equal => None,
+        }
+    }
+    // 删除选中的文本，没有选区时返回 false
+    fn delete_selection(&mut self) -> bool {
+        let Some((start, end)) = self.selection() else {
+            return false;
+        };
+        self.document.delete_range(&start, &end);
+        self.cursor_position = start;
+        self.selection_anchor = None;
+        true
+    }
+
+    // 复制选区，没有选区时复制当前整行；cut 为 true 时同时删除
+    fn copy(&mut self, cut: bool) -> Result<(), std::io::Error> {
+        let clipboard = if let Some((start, end)) = self.selection() {
+            let text = self.document.text_range(&start, &end);
+            if cut {
+                self.delete_selection();
+            }
+            Clipboard {
+                text,
+                linewise: false,
+            }
         } else {
-            self.status_message = StatusMessage::from("Error writing file!".to_string());
+            let y = self.cursor_position.y;
+            let Some(row) = self.document.row(y) else {
+                return Ok(());
+            };
+            if cut {
+                self.document
+                    .delete_range(&Position { x: 0, y }, &Position { x: 0, y: y + 1 });
+                self.cursor_position = Position { x: 0, y };
+            }
+            Clipboard {
+                text: format!("{}\n", row.substring(0, row.len())),
+                linewise: true,
+            }
+        };
+        self.terminal.set_clipboard(&clipboard.text)?;
+        self.clipboard = Some(clipboard);
+        Ok(())
+    }
+    fn paste(&mut self) {
+        let Some(clipboard) = &self.clipboard else {
+            self.status_message = StatusMessage::from("Clipboard is empty".to_string());
+            return;
+        };
+        let text = clipboard.text.clone();
+        if clipboard.linewise && self.selection().is_none() {
+            let at = Position {
+                x: 0,
+                y: self.cursor_position.y,
+            };
+            self.document.insert_str(&at, &text);
+            self.cursor_position = at;
+        } else {
+            self.insert_text(&text);
+        }
+        self.selection_anchor = None;
+    }
+    // 用 text 替换选区或插入到光标处
+    fn insert_text(&mut self, text: &str) {
+        self.delete_selection();
+        self.cursor_position = self.document.insert_str(&self.cursor_position, text);
+        self.selection_anchor = None;
+    }
+
+    // 选中光标所在的语法节点，已有选区时扩大到上一层节点
+    fn expand_selection(&mut self) {
+        let (start, end) = self
+            .selection()
+            .unwrap_or_else(|| (self.cursor_position.clone(), self.cursor_position.clone()));
+        match self.document.enclosing_node(&start, &end) {
+            Some((start, end)) => {
+                self.selection_anchor = Some(start);
+                self.cursor_position = end;
+            }
+            None => {
+                self.status_message = StatusMessage::from("No syntax tree for this file".to_string());
+            }
+        }
+    }
+
+    // Ctrl 组合键绑定的命令
+    fn command(&self, code: KeyCode) -> Option<Command> {
+        match code {
+            KeyCode::Char(key) => self.config.command(key),
+            _ => None,
+        }
+    }
+    // 命令的按键，用于提示信息
+    fn key_name(&self, command: Command) -> String {
+        self.config.key(command).unwrap_or_else(|| "the bound key".to_string())
+    }
+    // 会修改文档的输入
+    fn is_edit(&self, event: &Event) -> bool {
+        match event {
+            Event::Paste(_) => true,
+            Event::Key(code, KeyModifiers::CONTROL) => matches!(
+                self.command(*code),
+                Some(Command::Cut | Command::Paste | Command::Replace | Command::Undo | Command::Redo | Command::LineEnding)
+            ),
+            Event::Key(code, KeyModifiers::NONE | KeyModifiers::SHIFT) => matches!(
+                code,
+                KeyCode::Delete | KeyCode::Backspace | KeyCode::Enter | KeyCode::Char(_)
+            ),
+            _ => false,
         }
     }
 
     fn process_keypress(&mut self) -> Result<(), std::io::Error> {
-        let pressed_key = self.terminal.read_key()?;
-        match pressed_key {
-            (code, KeyModifiers::CONTROL) => match code {
-                KeyCode::Char('x') => {
-                    if self.quit_times > 0 && self.document.is_dirty() {
+        let event = self.terminal.read_key()?;
+        self.check_files()?;
+        // 只在空闲时写交换文件，输入时不做磁盘操作
+        if matches!(event, Event::Idle) {
+            self.write_swaps();
+        }
+        // 十六进制模式下只能输入十六进制数字改写字节
+        if self.document.is_hex() && self.is_edit(&event) {
+            match event {
+                Event::Key(KeyCode::Char(c), KeyModifiers::NONE | KeyModifiers::SHIFT)
+                    if c.is_ascii_hexdigit() =>
+                {
+                    let value = c.to_digit(16).unwrap_or_default() as u8;
+                    self.snap_hex_cursor();
+                    if self.document.set_nibble(&self.cursor_position, value) {
+                        self.move_cursor(KeyCode::Right);
+                    }
+                    self.selection_anchor = None;
+                }
+                _ => {
+                    self.status_message =
+                        StatusMessage::from("Hex view: type hex digits to overwrite bytes".to_string());
+                }
+            }
+            self.scroll();
+            return Ok(());
+        }
+        if self.document.is_read_only() && self.is_edit(&event) {
+            self.status_message = StatusMessage::from(format!(
+                "Read-only: file is not valid {}. Press {} to reopen with another encoding.",
+                self.document.encoding(),
+                self.key_name(Command::Reopen)
+            ));
+            return Ok(());
+        }
+        match event {
+            Event::Idle => return Ok(()),
+            Event::Resize => self.resize()?,
+            // 滚轮只移动指针下窗口的视口，不移动光标
+            Event::Mouse(kind @ (MouseEventKind::ScrollUp | MouseEventKind::ScrollDown), screen) => {
+                if let Some((index, _)) = self.window_at(&screen) {
+                    let forward = kind == MouseEventKind::ScrollDown;
+                    if index == self.current_window {
+                        self.scroll_view(WHEEL_LINES, forward);
+                    } else {
+                        self.in_window(index, |editor| editor.scroll_view(WHEEL_LINES, forward));
+                    }
+                }
+                return Ok(());
+            }
+            Event::Mouse(kind, screen) => self.click(kind, &screen),
+            Event::Paste(text) => {
+                // 终端粘贴的换行可能是 \r
+                self.insert_text(&text.replace("\r\n", "\n").replace('\r', "\n"));
+            }
+            Event::Key(code, KeyModifiers::CONTROL) => {
+                let command = self.command(code);
+                // 剪贴板与扩大选区命令需要用到选区
+                if !matches!(
+                    command,
+                    Some(Command::Copy | Command::Cut | Command::Paste | Command::ExpandSelection)
+                ) {
+                    self.selection_anchor = None;
+                }
+                let Some(command) = command else {
+                    return Ok(());
+                };
+                match command {
+                Command::Quit => {
+                    let dirty = self.dirty_buffers();
+                    if self.quit_times > 0 && !dirty.is_empty() {
                         self.status_message = StatusMessage::from(format!(
-                            "WARNING! File has unsaved changes. Press Ctrl-Q {} more times to quit.",
+                            "WARNING! Unsaved changes in {}. Press {} {} more times to quit.",
+                            dirty.join(", "),
+                            self.key_name(Command::Quit),
                             self.quit_times
                         ));
                         self.quit_times -= 1;
                         return Ok(());
                     }
                     self.should_quit = true;
+                    // 正常退出，不再需要交换文件
+                    self.swap_writer.flush();
+                    for index in 0..self.buffers.len() {
+                        let _ = self.buffer_document_mut(index).remove_swap();
+                    }
                 }
 
-                KeyCode::Char('s') => {
+                Command::Save => {
                     self.save();
                 }
-                _ => (),
-            },
-            (key, KeyModifiers::NONE) => match key {
-                KeyCode::Up
+                Command::Find => {
+                    self.search();
+                }
+                Command::Replace => {
+                    self.replace()?;
+                }
+                Command::Copy => self.copy(false)?,
+                Command::Cut => self.copy(true)?,
+                Command::Paste => self.paste(),
+                Command::ExpandSelection => self.expand_selection(),
+                Command::ParentFunction => match self.document.parent_function(&self.cursor_position) {
+                    Some(position) => self.cursor_position = position,
+                    None => self.status_message = StatusMessage::from("No enclosing function".to_string()),
+                },
+                Command::LineNumbers => {
+                    self.line_numbers = match self.line_numbers {
+                        LineNumbers::Off => LineNumbers::Absolute,
+                        LineNumbers::Absolute => LineNumbers::Relative,
+                        LineNumbers::Relative => LineNumbers::Off,
+                    };
+                }
+                Command::Wrap => {
+                    self.soft_wrap = !self.soft_wrap;
+                    self.offset.x = 0;
+                }
+                Command::NextBuffer => {
+                    self.switch_buffer((self.current_buffer + 1) % self.buffers.len());
+                }
+                Command::Buffers => self.pick_buffer()?,
+                Command::Window => self.window_command()?,
+                Command::Open => self.open()?,
+                Command::FindFile => self.find_file()?,
+                Command::LineEnding => self.convert_line_endings()?,
+                Command::Reopen => self.reopen_with_encoding()?,
+                Command::Hex => self.toggle_hex(),
+                Command::Undo => match self.document.undo() {
+                    Some(position) => self.cursor_position = position,
+                    None => self.status_message = StatusMessage::from("Nothing to undo".to_string()),
+                },
+                Command::Redo => match self.document.redo() {
+                    Some(position) => self.cursor_position = position,
+                    None => self.status_message = StatusMessage::from("Nothing to redo".to_string()),
+                },
+                }
+            }
+            // Shift+方向键扩展选区
+            Event::Key(
+                key @ (KeyCode::Up
                 | KeyCode::Down
                 | KeyCode::Left
                 | KeyCode::Right
                 | KeyCode::PageUp
                 | KeyCode::PageDown
                 | KeyCode::Home
-                | KeyCode::End => {
-                    self.move_cursor(key);
-                }
-                // 删除
-                KeyCode::Delete => {
-                    self.document.delete(&self.cursor_position);
+                | KeyCode::End),
+                KeyModifiers::SHIFT,
+            ) => {
+                if self.selection_anchor.is_none() {
+                    self.selection_anchor = Some(self.cursor_position.clone());
                 }
-                // 退格
-                KeyCode::Backspace => {
-                    if self.cursor_position.x > 0 || self.cursor_position.y > 0 {
+                self.document.seal_history();
+                self.move_cursor(key);
+            }
+            Event::Key(key, KeyModifiers::NONE | KeyModifiers::SHIFT) => {
+                match key {
+                    KeyCode::Up
+                    | KeyCode::Down
+                    | KeyCode::Left
+                    | KeyCode::Right
+                    | KeyCode::PageUp
+                    | KeyCode::PageDown
+                    | KeyCode::Home
+                    | KeyCode::End => {
+                        self.document.seal_history();
+                        self.move_cursor(key);
+                    }
+                    // 有选区时删除选区
+                    KeyCode::Delete | KeyCode::Backspace if self.selection().is_some() => {
+                        self.delete_selection();
+                    }
+                    // 删除
+                    KeyCode::Delete => {
+                        self.document.delete(&self.cursor_position);
+                    }
+                    // 退格
+                    KeyCode::Backspace
+                        if self.cursor_position.x > 0 || self.cursor_position.y > 0 =>
+                    {
                         self.move_cursor(KeyCode::Left);
                         self.document.delete(&self.cursor_position);
                     }
+                    //换行
+                    KeyCode::Enter => {
+                        self.delete_selection();
+                        self.document.insert(&self.cursor_position, '\n');
+                        self.move_cursor(KeyCode::Right);
+                    }
+                    // 字符
+                    KeyCode::Char(c) => {
+                        self.delete_selection();
+                        self.document.insert(&self.cursor_position, c);
+                        self.move_cursor(KeyCode::Right);
+                    }
+                    _ => (),
                 }
-                //换行
-                KeyCode::Enter => {
-                    self.document.insert(&self.cursor_position, '\n');
-                    self.move_cursor(KeyCode::Right);
-                }
-                // 字符
-                KeyCode::Char(c) => {
-                    self.document.insert(&self.cursor_position, c);
-                    self.move_cursor(KeyCode::Right);
-                }
-                _ => (),
-            },
+                self.selection_anchor = None;
+            }
             _ => (),
         }
+        self.snap_hex_cursor();
         self.scroll();
-        if self.quit_times < QUIT_TIMES {
-            self.quit_times = QUIT_TIMES;
+        if self.quit_times < self.config.quit_times {
+            self.quit_times = self.config.quit_times;
             self.status_message = StatusMessage::from(String::new());
         }
         Ok(())
     }
-    fn prompt(&mut self, prompt: &str) -> Result<Option<String>, std::io::Error> {
+    // 空输入与 Esc 一样返回 None
+    fn prompt<C>(&mut self, prompt: &str, callback: C) -> Result<Option<String>, std::io::Error>
+    where
+        C: FnMut(&mut Self, KeyCode, &mut String),
+    {
+        Ok(self.read_prompt(prompt, callback)?.filter(|result| !result.is_empty()))
+    }
+    // 只有 Esc 返回 None，回车时即使输入为空也返回
+    fn read_prompt<C>(&mut self, prompt: &str, mut callback: C) -> Result<Option<String>, std::io::Error>
+    where
+        C: FnMut(&mut Self, KeyCode, &mut String),
+    {
         let mut result = String::new();
 
         loop {
             self.status_message = StatusMessage::from(format!("{prompt}{result}"));
             self.refresh_screen()?;
-            if let (code, KeyModifiers::NONE) = self.terminal.read_key()? {
-                match code {
-                    KeyCode::Enter => {
-                        break;
-                    }
-                    KeyCode::Backspace => {
-                        if !result.is_empty() {
-                            result.truncate(result.len() - 1);
-                        }
+            let code = match self.terminal.read_key()? {
+                Event::Key(code, KeyModifiers::NONE | KeyModifiers::SHIFT) => code,
+                // 粘贴到提示栏时只取可见字符
+                Event::Paste(text) => {
+                    result.extend(text.chars().filter(|c| !c.is_control()));
+                    KeyCode::Null
+                }
+                Event::Resize => {
+                    self.resize()?;
+                    continue;
+                }
+                Event::Key(..) | Event::Mouse(..) | Event::Idle => continue,
+            };
+            match code {
+                KeyCode::Enter => {
+                    break;
+                }
+                KeyCode::Esc => {
+                    self.status_message = StatusMessage::from(String::new());
+                    return Ok(None);
+                }
+                KeyCode::Backspace => {
+                    result.pop();
+                }
+                KeyCode::Char(c) => result.push(c),
+                _ => (),
+            }
+            callback(self, code, &mut result);
+        }
+        self.status_message = StatusMessage::from(String::new());
+        Ok(Some(result))
+    }
+    // 第 index 个缓冲区的文档，当前缓冲区的文档在 self.document 中
+    fn buffer_document(&self, index: usize) -> &Document {
+        if index == self.current_buffer {
+            &self.document
+        } else {
+            &self.buffers[index].document
+        }
+    }
+    fn buffer_document_mut(&mut self, index: usize) -> &mut Document {
+        if index == self.current_buffer {
+            &mut self.document
+        } else {
+            &mut self.buffers[index].document
+        }
+    }
+    fn buffer_name(&self, index: usize) -> String {
+        self.buffer_document(index)
+            .file_name
+            .clone()
+            .unwrap_or_else(|| "[No Name]".to_string())
+    }
+    // 有未保存修改的缓冲区名
+    fn dirty_buffers(&self) -> Vec<String> {
+        (0..self.buffers.len())
+            .filter(|index| self.buffer_document(*index).is_dirty())
+            .map(|index| self.buffer_name(index))
+            .collect()
+    }
+    // 当前缓冲区换回列表，再换入第 index 个
+    fn switch_buffer(&mut self, index: usize) {
+        if index == self.current_buffer || index >= self.buffers.len() {
+            return;
+        }
+        for slot in [self.current_buffer, index] {
+            let buffer = &mut self.buffers[slot];
+            mem::swap(&mut buffer.document, &mut self.document);
+            mem::swap(&mut buffer.cursor_position, &mut self.cursor_position);
+            mem::swap(&mut buffer.offset, &mut self.offset);
+        }
+        self.current_buffer = index;
+        self.selection_anchor = None;
+        self.highlighted_match = None;
+    }
+    // 打开文件到新缓冲区，已打开时直接切换过去
+    fn open_file(&mut self, file_name: &str) -> Result<(), std::io::Error> {
+        // 不同写法的路径指向同一文件时切换到已打开的缓冲区，还不存在的文件比较原始路径
+        let canonical = |name: &str| fs::canonicalize(name).unwrap_or_else(|_| PathBuf::from(name));
+        let path = canonical(file_name);
+        if let Some(index) = (0..self.buffers.len()).find(|index| {
+            self.buffer_document(*index)
+                .file_name
+                .as_deref()
+                .is_some_and(|name| canonical(name) == path)
+        }) {
+            self.switch_buffer(index);
+            return Ok(());
+        }
+        match Document::open(file_name) {
+            Ok(document) => {
+                self.buffers.push(Buffer {
+                    document,
+                    ..Buffer::default()
+                });
+                self.switch_buffer(self.buffers.len() - 1);
+                self.watch();
+                self.check_swap()?;
+            }
+            Err(error) => {
+                self.status_message =
+                    StatusMessage::from(format!("ERR:Could not Open file:{file_name}: {error}"));
+            }
+        }
+        Ok(())
+    }
+    // 当前文件留有交换文件时询问恢复、比较还是丢弃
+    fn check_swap(&mut self) -> Result<(), std::io::Error> {
+        let Some(file_name) = self.document.file_name.clone() else {
+            return Ok(());
+        };
+        let Some(found) = swap::read(&file_name) else {
+            return Ok(());
+        };
+        let running = if swap::is_running(found.pid) {
+            format!(" (process {} is still running)", found.pid)
+        } else {
+            String::new()
+        };
+        let prompt =
+            format!("Swap file found for {file_name}{running}. r = recover | d = diff | x = discard | Esc = keep: ");
+        // 比较时暂时换上比较结果，记下原来的文档与位置
+        let mut stashed: Option<(Document, Position, Position)> = None;
+        let answer = loop {
+            let answer = self.prompt(&prompt, |_, _, _| {})?;
+            if let Some((document, cursor_position, offset)) = stashed.take() {
+                self.document = document;
+                self.cursor_position = cursor_position;
+                self.offset = offset;
+            }
+            if answer.as_deref().map(str::trim) != Some("d") {
+                break answer;
+            }
+            let diff = Document::from_text(&swap::diff(&self.document.text(), &found.text));
+            stashed = Some((
+                mem::replace(&mut self.document, diff),
+                mem::take(&mut self.cursor_position),
+                mem::take(&mut self.offset),
+            ));
+        };
+        self.status_message = StatusMessage::from(match answer.as_deref().map(str::trim) {
+            Some("r") => {
+                self.document.recover(&found.text);
+                self.cursor_position = Position::default();
+                self.offset = Position::default();
+                // 接管交换文件，保存或退出时一并删除
+                if let Some(job) = self.document.swap_job() {
+                    self.swap_writer.submit(job);
+                }
+                format!("Recovered {file_name} from its swap file")
+            }
+            Some("x") => match swap::remove(&file_name) {
+                Ok(()) => format!("Discarded swap file of {file_name}"),
+                Err(error) => format!("Could not remove swap file: {error}"),
+            },
+            _ => format!("Kept swap file {}", swap::path(&file_name).display()),
+        });
+        Ok(())
+    }
+    // 把所有缓冲区未保存的修改写入交换文件
+    fn write_swaps(&mut self) {
+        for index in 0..self.buffers.len() {
+            if let Some(job) = self.buffer_document_mut(index).swap_job() {
+                self.swap_writer.submit(job);
+            }
+        }
+        // 上一轮后台写入出的错
+        if let Some(error) = self.swap_writer.errors().last() {
+            self.status_message = StatusMessage::from(format!("Error writing swap file: {error}"));
+        }
+    }
+    // 输入路径打开文件，Tab 补全
+    fn open(&mut self) -> Result<(), std::io::Error> {
+        let file_name = self.prompt("Open: ", |editor, key, input| {
+            editor.overlay = if key == KeyCode::Tab {
+                finder::complete(input)
+            } else {
+                Vec::new()
+            };
+            // 只有一个候选时已经补全，不必再列出
+            if editor.overlay.len() == 1 {
+                editor.overlay.clear();
+            }
+        })?;
+        self.overlay.clear();
+        if let Some(file_name) = file_name {
+            self.open_file(&file_name)?;
+        }
+        Ok(())
+    }
+    // 模糊查找当前目录下的文件，上下键选择
+    fn find_file(&mut self) -> Result<(), std::io::Error> {
+        let files = finder::files(Path::new("."));
+        let mut ranked: Vec<String> = files.iter().take(OVERLAY_ITEMS).cloned().collect();
+        let mut selected = 0;
+        self.overlay.clone_from(&ranked);
+        self.overlay_selected = Some(selected);
+        // 不输入直接回车打开列表里选中的文件，只有 Esc 放弃
+        let query = self.read_prompt("Find file: ", |editor, key, query| {
+            match key {
+                KeyCode::Up => selected = selected.saturating_sub(1),
+                KeyCode::Down => selected = selected.saturating_add(1).min(ranked.len().saturating_sub(1)),
+                _ => {
+                    ranked = finder::rank(&files, query);
+                    ranked.truncate(OVERLAY_ITEMS);
+                    selected = 0;
+                }
+            }
+            editor.overlay.clone_from(&ranked);
+            editor.overlay_selected = Some(selected);
+        })?;
+        self.overlay.clear();
+        self.overlay_selected = None;
+        if query.is_some() {
+            match ranked.get(selected) {
+                Some(file_name) => self.open_file(file_name)?,
+                None => self.status_message = StatusMessage::from("No matching file".to_string()),
+            }
+        }
+        Ok(())
+    }
+    // 转换当前文档保存时使用的换行符，eol 切换文件末尾的换行
+    fn convert_line_endings(&mut self) -> Result<(), std::io::Error> {
+        let prompt = "Line ending (lf, crlf, cr), eol to toggle final newline: ";
+        let Some(input) = self.prompt(prompt, |_, _, _| {})? else {
+            return Ok(());
+        };
+        let line_ending = match input.trim().to_lowercase().as_str() {
+            "lf" => LineEnding::Lf,
+            "crlf" => LineEnding::CrLf,
+            "cr" => LineEnding::Cr,
+            "eol" => {
+                let final_newline = !self.document.has_final_newline();
+                self.document.set_final_newline(final_newline);
+                self.status_message = StatusMessage::from(
+                    if final_newline { "Final newline added" } else { "Final newline removed" }.to_string(),
+                );
+                return Ok(());
+            }
+            _ => {
+                self.status_message = StatusMessage::from(format!("Unknown line ending: {input}"));
+                return Ok(());
+            }
+        };
+        self.document.set_line_ending(line_ending);
+        self.status_message = StatusMessage::from(format!("Line endings set to {line_ending}"));
+        Ok(())
+    }
+    // 按指定编码重新读取当前文件
+    fn reopen_with_encoding(&mut self) -> Result<(), std::io::Error> {
+        let Some(file_name) = self.document.file_name.clone() else {
+            self.status_message = StatusMessage::from("No file to reopen".to_string());
+            return Ok(());
+        };
+        if self.document.is_dirty() {
+            self.status_message = StatusMessage::from("Save the file before reopening it".to_string());
+            return Ok(());
+        }
+        let prompt = "Reopen with encoding (e.g. utf-8, gbk, shift_jis, latin1, utf-16le): ";
+        let Some(label) = self.prompt(prompt, |_, _, _| {})? else {
+            return Ok(());
+        };
+        let Some(encoding) = FileEncoding::for_label(&label) else {
+            self.status_message = StatusMessage::from(format!("Unknown encoding: {label}"));
+            return Ok(());
+        };
+        match Document::open_with_encoding(&file_name, Some(encoding)) {
+            Ok(document) => {
+                self.document = document;
+                // 解码后行的长度可能不同
+                self.clamp_cursor();
+                self.status_message = StatusMessage::from(if self.document.is_read_only() {
+                    format!("File is not valid {encoding}, opened read-only")
+                } else {
+                    format!("Reopened as {encoding}")
+                });
+            }
+            Err(error) => {
+                self.status_message =
+                    StatusMessage::from(format!("ERR:Could not Open file:{file_name}: {error}"));
+            }
+        }
+        Ok(())
+    }
+    // 在十六进制视图与文本视图之间切换，从磁盘重新读取文件
+    fn toggle_hex(&mut self) {
+        let Some(file_name) = self.document.file_name.clone() else {
+            self.status_message = StatusMessage::from("No file to view as hex".to_string());
+            return;
+        };
+        if self.document.is_dirty() {
+            self.status_message = StatusMessage::from("Save the file before switching views".to_string());
+            return;
+        }
+        let document = if self.document.is_hex() {
+            Document::open_with_encoding(&file_name, None)
+        } else {
+            Document::open_hex(&file_name)
+        };
+        match document {
+            Ok(document) => {
+                self.document = document;
+                self.cursor_position = Position::default();
+                self.offset = Position::default();
+                self.snap_hex_cursor();
+            }
+            Err(error) => {
+                self.status_message =
+                    StatusMessage::from(format!("ERR:Could not Open file:{file_name}: {error}"));
+            }
+        }
+    }
+    // 十六进制模式下光标只停在字节的半字节上
+    fn snap_hex_cursor(&mut self) {
+        if !self.document.is_hex() {
+            return;
+        }
+        let y = self.cursor_position.y.min(self.document.len().saturating_sub(1));
+        let x = hex::nibble_at(self.cursor_position.x, self.document.hex_count(y))
+            .map_or(0, hex::nibble_column);
+        self.cursor_position = Position { x, y };
+    }
+    // 十六进制模式下左右移动一个半字节，Home/End 到行内首尾字节
+    fn move_hex(&mut self, key: KeyCode) {
+        let Position { x, mut y } = self.cursor_position;
+        let count = self.document.hex_count(y);
+        let Some(mut nibble) = hex::nibble_at(x, count) else {
+            return;
+        };
+        let last = count * 2 - 1;
+        match key {
+            KeyCode::Left if nibble > 0 => nibble -= 1,
+            KeyCode::Left if y > 0 => {
+                y -= 1;
+                nibble = self.document.hex_count(y) * 2 - 1;
+            }
+            KeyCode::Right if nibble < last => nibble += 1,
+            KeyCode::Right if self.document.hex_count(y + 1) > 0 => {
+                y += 1;
+                nibble = 0;
+            }
+            KeyCode::Home => nibble = 0,
+            KeyCode::End => nibble = last,
+            _ => (),
+        }
+        self.cursor_position = Position {
+            x: hex::nibble_column(nibble),
+            y,
+        };
+    }
+    // 在提示栏列出缓冲区，按序号或文件名切换
+    fn pick_buffer(&mut self) -> Result<(), std::io::Error> {
+        let list: Vec<String> = (0..self.buffers.len())
+            .map(|index| {
+                let modified = if self.buffer_document(index).is_dirty() { "+" } else { "" };
+                format!("{}:{}{modified}", index + 1, self.buffer_name(index))
+            })
+            .collect();
+        let Some(answer) = self.prompt(&format!("{} | Buffer: ", list.join(" ")), |_, _, _| ())? else {
+            return Ok(());
+        };
+        let index = match answer.parse::<usize>() {
+            Ok(number) => number.checked_sub(1).filter(|index| *index < self.buffers.len()),
+            Err(_) => (0..self.buffers.len())
+                .find(|index| self.buffer_name(*index).contains(answer.as_str())),
+        };
+        match index {
+            Some(index) => self.switch_buffer(index),
+            None => self.status_message = StatusMessage::from(format!("No buffer matches {answer}")),
+        }
+        Ok(())
+    }
+    // 单击移动光标并切换到所在窗口，拖动选择，双击选中单词
+    fn click(&mut self, kind: MouseEventKind, screen: &Position) {
+        if kind == MouseEventKind::Down(MouseButton::Left) {
+            let Some((index, _)) = self.window_at(screen) else {
+                return;
+            };
+            self.focus_window(index);
+        }
+        // 拖出窗口时按窗口边缘处理
+        let area = self.area();
+        let relative = Position {
+            x: screen.x.saturating_sub(area.x),
+            y: screen
+                .y
+                .saturating_sub(area.y)
+                .min(area.height.saturating_sub(1)),
+        };
+        let Some(position) = self.position_at(&relative) else {
+            return;
+        };
+        self.document.seal_history();
+        match kind {
+            MouseEventKind::Down(MouseButton::Left) => {
+                let double_click = self.last_click.as_ref().is_some_and(|(time, last)| {
+                    time.elapsed() < DOUBLE_CLICK_TIME && *last == position
+                });
+                self.selection_anchor = None;
+                self.cursor_position = position.clone();
+                if double_click {
+                    if let Some(row) = self.document.row(position.y) {
+                        let (start, end) = row.word_bounds(position.x);
+                        self.selection_anchor = Some(Position { x: start, y: position.y });
+                        self.cursor_position = Position { x: end, y: position.y };
                     }
-                    KeyCode::Char(c) => result.push(c),
-                    _ => (),
+                    self.last_click = None;
+                } else {
+                    self.last_click = Some((Instant::now(), position));
                 }
             }
+            MouseEventKind::Drag(MouseButton::Left) => {
+                if self.selection_anchor.is_none() {
+                    self.selection_anchor = Some(self.cursor_position.clone());
+                }
+                self.cursor_position = position;
+            }
+            _ => (),
+        }
+    }
+    // 窗口内坐标对应的文档位置，文本区以下对应文档末尾，状态栏返回 None
+    fn position_at(&self, screen: &Position) -> Option<Position> {
+        if screen.y >= self.area().height {
+            return None;
+        }
+        let Some((y, line, columns)) = self.screen_lines().get(screen.y).cloned() else {
+            return Some(Position {
+                x: 0,
+                y: self.document.len(),
+            });
+        };
+        let row = self.document.row(y)?;
+        let column = columns
+            .start
+            .saturating_add(screen.x.saturating_sub(self.gutter_width()));
+        let mut x = row.index_at_column(column, self.tab_width);
+        // 折行时点在视觉行末尾之后，停在该视觉行最后一个字位
+        if self.soft_wrap {
+            if let Some(next) = self.visual_lines(y).get(line.saturating_add(1)) {
+                x = x.min(next.saturating_sub(1));
+            }
+        }
+        Some(Position { x, y })
+    }
+    // 移动视口 count 行，光标保持不动
+    fn scroll_view(&mut self, count: usize, forward: bool) {
+        let len = self.document.len();
+        if !self.soft_wrap {
+            self.offset.y = if forward {
+                self.offset.y.saturating_add(count).min(len)
+            } else {
+                self.offset.y.saturating_sub(count)
+            };
+            return;
+        }
+        let (mut y, mut line) = (self.offset.y, self.offset.x);
+        if forward {
+            for _ in 0..count {
+                if line.saturating_add(1) < self.visual_lines(y).len() {
+                    line += 1;
+                } else if y < len {
+                    y += 1;
+                    line = 0;
+                } else {
+                    break;
+                }
+            }
+        } else {
+            (y, line) = self.visual_back((y, line), count);
+        }
+        (self.offset.y, self.offset.x) = (y, line);
+    }
+    // 整个编辑区（不含消息栏）里各窗口的区域与分隔线，窗口区域含其状态栏
+    fn arrange(&self) -> (Vec<(usize, Rect)>, Vec<Rect>) {
+        let size = self.terminal.size();
+        let screen = Rect {
+            x: 0,
+            y: 0,
+            width: size.width as usize,
+            height: (size.height as usize).saturating_add(1),
+        };
+        let mut windows = Vec::new();
+        let mut separators = Vec::new();
+        self.layout.arrange(screen, &mut windows, &mut separators);
+        (windows, separators)
+    }
+    // 当前窗口的文本区
+    fn area(&self) -> Rect {
+        let (windows, _) = self.arrange();
+        let rect = windows
+            .into_iter()
+            .find(|(index, _)| *index == self.current_window)
+            .map_or_else(Rect::default, |(_, rect)| rect);
+        Rect {
+            height: rect.height.saturating_sub(1),
+            ..rect
         }
+    }
+    // 屏幕坐标所在的窗口
+    fn window_at(&self, screen: &Position) -> Option<(usize, Rect)> {
+        self.arrange().0.into_iter().find(|(_, rect)| {
+            (rect.x..rect.x.saturating_add(rect.width)).contains(&screen.x)
+                && (rect.y..rect.y.saturating_add(rect.height)).contains(&screen.y)
+        })
+    }
+    // 保存当前窗口的状态，换入第 index 个窗口
+    fn focus_window(&mut self, index: usize) {
+        if index == self.current_window || index >= self.windows.len() {
+            return;
+        }
+        let window = &mut self.windows[self.current_window];
+        window.buffer = self.current_buffer;
+        window.cursor_position = self.cursor_position.clone();
+        window.offset = self.offset.clone();
+        self.load_window(index);
+    }
+    fn load_window(&mut self, index: usize) {
+        self.current_window = index;
+        self.switch_buffer(self.windows[index].buffer);
+        self.cursor_position = self.windows[index].cursor_position.clone();
+        self.offset = self.windows[index].offset.clone();
+        // 其他窗口可能改短了同一个文档
+        self.clamp_cursor();
+        self.selection_anchor = None;
+        self.highlighted_match = None;
+    }
+    // 文档变短后把光标限制在文档范围内
+    fn clamp_cursor(&mut self) {
+        let y = self.cursor_position.y.min(self.document.len());
+        let x = self
+            .document
+            .row(y)
+            .map_or(0, |row| self.cursor_position.x.min(row.len()));
+        self.cursor_position = Position { x, y };
+    }
+    // 临时切换到其他窗口执行 f，之后回到当前窗口并恢复选区
+    fn in_window<R>(&mut self, index: usize, f: impl FnOnce(&mut Self) -> R) -> R {
+        let active = self.current_window;
+        let selection_anchor = self.selection_anchor.take();
+        let highlighted_match = self.highlighted_match.take();
+        self.focus_window(index);
+        let result = f(self);
+        self.focus_window(active);
+        self.selection_anchor = selection_anchor;
+        self.highlighted_match = highlighted_match;
+        result
+    }
+    // Ctrl-G 之后的窗口命令
+    fn window_command(&mut self) -> Result<(), std::io::Error> {
+        self.status_message = StatusMessage::from(
+            "Window: s = split | v = vsplit | w = next | c = close | +/- = resize".to_string(),
+        );
+        self.refresh_screen()?;
+        let code = loop {
+            match self.terminal.read_key()? {
+                Event::Key(code, KeyModifiers::NONE | KeyModifiers::SHIFT) => break code,
+                Event::Resize => {
+                    self.resize()?;
+                    self.refresh_screen()?;
+                }
+                _ => (),
+            }
+        };
         self.status_message = StatusMessage::from(String::new());
-        if result.is_empty() {
-            return Ok(None);
+        match code {
+            KeyCode::Char('s') => self.split_window(SplitDirection::Horizontal),
+            KeyCode::Char('v') => self.split_window(SplitDirection::Vertical),
+            KeyCode::Char('w') | KeyCode::Tab => {
+                let order = self.layout.windows();
+                let position = order
+                    .iter()
+                    .position(|index| *index == self.current_window)
+                    .unwrap_or_default();
+                self.focus_window(order[(position + 1) % order.len()]);
+            }
+            KeyCode::Char('c') => self.close_window(),
+            KeyCode::Char(c @ ('+' | '-')) => {
+                let delta = if c == '+' { RESIZE_STEP } else { -RESIZE_STEP };
+                if !self.layout.resize(self.current_window, delta) {
+                    self.status_message = StatusMessage::from("Only one window".to_string());
+                }
+            }
+            _ => (),
         }
-        Ok(Some(result))
+        self.terminal.clear_screen()
+    }
+    // 新窗口显示同一缓冲区的同一位置，并获得焦点
+    fn split_window(&mut self, direction: SplitDirection) {
+        let index = self.windows.len();
+        self.windows.push(Window {
+            buffer: self.current_buffer,
+            cursor_position: self.cursor_position.clone(),
+            offset: self.offset.clone(),
+        });
+        self.layout.split(self.current_window, index, direction);
+        self.focus_window(index);
+    }
+    fn close_window(&mut self) {
+        if self.windows.len() == 1 {
+            self.status_message = StatusMessage::from("Cannot close the last window".to_string());
+            return;
+        }
+        // 焦点交给屏幕顺序上的前一个窗口
+        let order = self.layout.windows();
+        let position = order
+            .iter()
+            .position(|index| *index == self.current_window)
+            .unwrap_or_default();
+        let closed = self.current_window;
+        let mut next = order[if position == 0 { 1 } else { position - 1 }];
+        self.layout.remove(closed);
+        self.windows.remove(closed);
+        if next > closed {
+            next -= 1;
+        }
+        self.load_window(next);
+    }
+    // 终端大小改变后清屏并让光标回到可见范围，随后的刷新按新尺寸重绘
+    fn resize(&mut self) -> Result<(), std::io::Error> {
+        self.terminal.clear_screen()?;
+        self.scroll();
+        Ok(())
     }
     fn move_cursor(&mut self, key: KeyCode) {
-        let terminal_height = self.terminal.size().height as usize;
+        let horizontal = matches!(key, KeyCode::Left | KeyCode::Right | KeyCode::Home | KeyCode::End);
+        if self.document.is_hex() && horizontal {
+            self.move_hex(key);
+            return;
+        }
+        if self.soft_wrap
+            && matches!(key, KeyCode::Up | KeyCode::Down | KeyCode::PageUp | KeyCode::PageDown)
+        {
+            self.move_visual(key);
+            return;
+        }
+        let terminal_height = self.area().height;
         let Position { mut y, mut x } = self.cursor_position;
         let height = self.document.len();
         let mut width = if let Some(row) = self.document.row(y) {
@@ -203,13 +1428,10 @@ impl Editor {
         } else {
             0
         };
+        let column = self.cursor_column();
         match key {
             KeyCode::Up => y = y.saturating_sub(1),
-            KeyCode::Down => {
-                if y < height {
-                    y = y.saturating_add(1);
-                }
-            }
+            KeyCode::Down if y < height => y = y.saturating_add(1),
             KeyCode::Left => {
                 if x > 0 {
                     x -= 1;
@@ -230,13 +1452,7 @@ impl Editor {
                     x = 0;
                 }
             }
-            KeyCode::PageUp => {
-                y = if y > terminal_height {
-                    y - terminal_height
-                } else {
-                    0
-                }
-            }
+            KeyCode::PageUp => y = y.saturating_sub(terminal_height),
             KeyCode::PageDown => {
                 y = if y.saturating_add(terminal_height) < height {
                     y + terminal_height
@@ -249,6 +1465,10 @@ impl Editor {
             _ => (),
         }
         width = if let Some(row) = self.document.row(y) {
+            // 上下移动时保持显示列不变
+            if matches!(key, KeyCode::Up | KeyCode::Down | KeyCode::PageUp | KeyCode::PageDown) {
+                x = row.index_at_column(column, self.tab_width);
+            }
             row.len()
         } else {
             0
@@ -259,55 +1479,214 @@ impl Editor {
         self.cursor_position = Position { x, y };
     }
 
+    // 折行模式下按视觉行上下移动，并保持视觉行内的显示列
+    fn move_visual(&mut self, key: KeyCode) {
+        let steps = match key {
+            KeyCode::PageUp | KeyCode::PageDown => self.area().height,
+            _ => 1,
+        };
+        let forward = matches!(key, KeyCode::Down | KeyCode::PageDown);
+        let (mut line, column) = self.cursor_visual();
+        let mut y = self.cursor_position.y;
+        let mut starts = self.visual_lines(y);
+        for _ in 0..steps {
+            if forward {
+                if line.saturating_add(1) < starts.len() {
+                    line += 1;
+                } else if y < self.document.len() {
+                    y += 1;
+                    starts = self.visual_lines(y);
+                    line = 0;
+                } else {
+                    break;
+                }
+            } else if line > 0 {
+                line -= 1;
+            } else if y > 0 {
+                y -= 1;
+                starts = self.visual_lines(y);
+                line = starts.len().saturating_sub(1);
+            } else {
+                break;
+            }
+        }
+        let x = self.document.row(y).map_or(0, |row| {
+            let start = starts[line];
+            // 非最后一个视觉行的末尾位置属于下一视觉行
+            let end = starts
+                .get(line.saturating_add(1))
+                .map_or(row.len(), |next| next.saturating_sub(1).max(start));
+            let line_column = row.column(start, self.tab_width);
+            row.index_at_column(line_column.saturating_add(column), self.tab_width)
+                .clamp(start, end)
+        });
+        self.cursor_position = Position { x, y };
+    }
+    // 第 y 行折行后各视觉行起始的字位下标
+    fn visual_lines(&self, y: usize) -> Vec<usize> {
+        self.document
+            .row(y)
+            .map_or_else(|| vec![0], |row| row.wrap(self.text_width(), self.tab_width))
+    }
+    // 光标所在的视觉行及其在视觉行内的显示列
+    fn cursor_visual(&self) -> (usize, usize) {
+        let Position { x, y } = self.cursor_position;
+        let Some(row) = self.document.row(y) else {
+            return (0, 0);
+        };
+        let starts = row.wrap(self.text_width(), self.tab_width);
+        let line = starts.partition_point(|start| *start <= x).saturating_sub(1);
+        let column = row
+            .column(x, self.tab_width)
+            .saturating_sub(row.column(starts[line], self.tab_width));
+        (line, column)
+    }
+    // 光标在屏幕上的位置，不在视口内时返回 None
+    fn cursor_screen_position(&self) -> Option<Position> {
+        let gutter_width = self.gutter_width();
+        let height = self.area().height;
+        let y = self.cursor_position.y;
+        if !self.soft_wrap {
+            let column = self.cursor_column();
+            if y < self.offset.y
+                || y >= self.offset.y.saturating_add(height)
+                || column < self.offset.x
+            {
+                return None;
+            }
+            return Some(Position {
+                x: column.saturating_sub(self.offset.x).saturating_add(gutter_width),
+                y: y.saturating_sub(self.offset.y),
+            });
+        }
+        let (line, column) = self.cursor_visual();
+        if (y, line) < (self.offset.y, self.offset.x) {
+            return None;
+        }
+        // 从屏幕顶端数到光标所在的视觉行，超出屏幕高度即可停止
+        let mut rows_above = 0;
+        for index in self.offset.y..y {
+            rows_above += self.visual_lines(index).len();
+            if rows_above >= height.saturating_add(self.offset.x) {
+                return None;
+            }
+        }
+        let screen_y = rows_above.saturating_add(line).saturating_sub(self.offset.x);
+        (screen_y < height).then(|| Position {
+            x: column.saturating_add(gutter_width),
+            y: screen_y,
+        })
+    }
+    // 屏幕上每一行显示的文档行号、视觉行序号与显示列区间
+    fn screen_lines(&self) -> Vec<(usize, usize, Range<usize>)> {
+        let height = self.area().height;
+        let len = self.document.len();
+        if !self.soft_wrap {
+            let left = self.offset.x;
+            let columns = left..left.saturating_add(self.text_width());
+            return (self.offset.y..len.min(self.offset.y.saturating_add(height)))
+                .map(|index| (index, 0, columns.clone()))
+                .collect();
+        }
+        let mut lines = Vec::new();
+        let mut skip = self.offset.x;
+        for index in self.offset.y..len {
+            let Some(row) = self.document.row(index) else {
+                break;
+            };
+            let starts = row.wrap(self.text_width(), self.tab_width);
+            for (line, start) in starts.iter().enumerate().skip(skip) {
+                if lines.len() == height {
+                    return lines;
+                }
+                let end = starts.get(line.saturating_add(1)).copied().unwrap_or(row.len());
+                let columns = row.column(*start, self.tab_width)..row.column(end, self.tab_width);
+                lines.push((index, line, columns));
+            }
+            skip = 0;
+        }
+        lines
+    }
+    // 行号栏宽度：行数的位数加一个空格
+    fn gutter_width(&self) -> usize {
+        if self.line_numbers == LineNumbers::Off {
+            return 0;
+        }
+        self.document.len().max(1).to_string().len().saturating_add(1)
+    }
+    // 除去行号栏后的文本区宽度
+    fn text_width(&self) -> usize {
+        self.area().width.saturating_sub(self.gutter_width())
+    }
+    // 光标所在的显示列
+    fn cursor_column(&self) -> usize {
+        let Position { x, y } = self.cursor_position;
+        self.document
+            .row(y)
+            .map_or(0, |row| row.column(x, self.tab_width))
+    }
+    // 从 (y, line) 向上数 count 个视觉行
+    fn visual_back(&self, (mut y, mut line): (usize, usize), count: usize) -> (usize, usize) {
+        for _ in 0..count {
+            if line > 0 {
+                line -= 1;
+            } else if y > 0 {
+                y -= 1;
+                line = self.visual_lines(y).len().saturating_sub(1);
+            } else {
+                break;
+            }
+        }
+        (y, line)
+    }
     fn scroll(&mut self) {
+        if self.soft_wrap {
+            let height = self.area().height;
+            // 编辑后偏移所在行可能已变短
+            self.offset.x = self
+                .offset
+                .x
+                .min(self.visual_lines(self.offset.y).len().saturating_sub(1));
+            let cursor = (self.cursor_position.y, self.cursor_visual().0);
+            let top = self.visual_back(cursor, height.saturating_sub(1));
+            let offset = (self.offset.y, self.offset.x);
+            if cursor < offset {
+                (self.offset.y, self.offset.x) = cursor;
+            } else if top > offset {
+                (self.offset.y, self.offset.x) = top;
+            }
+            return;
+        }
         let Position { x, y } = self.cursor_position;
-        let width = self.terminal.size().width as usize;
-        let height = self.terminal.size().height as usize;
+        let column = self.cursor_column();
+        // 光标处字位的宽度，行尾按一列计
+        let cursor_width = self
+            .document
+            .row(y)
+            .map_or(0, |row| row.width(x, self.tab_width))
+            .max(1);
+        let width = self.text_width();
+        let height = self.area().height;
         let offset = &mut self.offset;
         if y < offset.y {
             offset.y = y;
         } else if y >= offset.y.saturating_add(height) {
             offset.y = y.saturating_sub(height).saturating_add(1);
         }
-        if x < offset.x {
-            offset.x = x;
-        } else if x >= offset.x.saturating_add(width) {
-            offset.x = x.saturating_sub(width).saturating_add(1);
-        }
-    }
-    pub fn default() -> Self{
-        // 获取文件名，初始化提示信息
-        let args: Vec<String> = env::args().collect();
-        let mut initial_status = String::from("HELP: Ctrl-S = save | Ctrl-X = quit");
-        // 打开文档
-        let mut document=Document::default();
-        if args.len() > 1 {
-            let file_name = &args[1];
-            let doc= Document::open(file_name);
-            match doc {
-                Ok(d) => {
-                    document=d;
-                },
-                Err(_) =>{
-                    initial_status = format!("ERR:Could not Open file:{file_name}");
-                },
-            }
-        }
-        Self {
-            should_quit: false,
-            terminal: Terminal::default().expect("Failed to initialize terminal"),
-            document,
-            cursor_position: Position::default(),
-            offset: Position::default(),
-            status_message: StatusMessage::from(initial_status),
-            quit_times:QUIT_TIMES
+        if column < offset.x {
+            offset.x = column;
+        } else if column.saturating_add(cursor_width) > offset.x.saturating_add(width) {
+            offset.x = column.saturating_add(cursor_width).saturating_sub(width);
         }
     }
-
-    fn draw_status_bar(&mut self)->Result<(),std::io::Error>{
+    // 窗口底部的状态栏，非当前窗口颜色较暗
+    fn draw_status_bar(&mut self, active: bool)->Result<(),std::io::Error>{
         let mut status;
-        let width = self.terminal.size().width as usize;
-        let modified_indicator = if self.document.is_dirty() {
+        let area = self.area();
+        let width = area.width;
+        let modified_indicator = if self.document.is_read_only() {
+            " (read-only)"
+        } else if self.document.is_dirty() {
             " (modified)"
         } else {
             ""
@@ -319,6 +1698,9 @@ impl Editor {
             file_name.truncate(20);
         }
         // 拼接文件信息
+        if self.buffers.len() > 1 {
+            file_name = format!("[{}/{}] {file_name}", self.current_buffer + 1, self.buffers.len());
+        }
         status = format!(
             "{} - {} lines {}",
             file_name,
@@ -326,63 +1708,240 @@ impl Editor {
             modified_indicator
         );
         // 展示当前行数
+        // 只在不是 UTF-8 时显示编码
+        let encoding = self.document.encoding();
+        let format = if self.document.is_hex() {
+            "HEX".to_string()
+        } else {
+            format!(
+                "{}{}{}{}",
+                if encoding.is_utf8() { String::new() } else { format!("{encoding} | ") },
+                self.document.line_ending(),
+                if self.document.has_mixed_line_endings() { " mixed" } else { "" },
+                if self.document.has_final_newline() { "" } else { " noeol" }
+            )
+        };
         let line_indicator = format!(
-            "{}/{}",
+            "{}{} | {} | {}/{}",
+            self.document.file_type(),
+            if self.soft_wrap { " | wrap" } else { "" },
+            format,
             self.cursor_position.y.saturating_add(1),
             self.document.len()
         );
         // 空白填充
         let len = status.len() + line_indicator.len();
         if width > status.len() {
-            status.push_str(&" ".repeat(width - len));
+            status.push_str(&" ".repeat(width.saturating_sub(len)));
         }
         status = format!("{status}{line_indicator}");
         status.truncate(width);
-        self.terminal.set_bg_color(STATUS_BG_COLOR)?;
-        self.terminal.set_fg_color(STATUS_FG_COLOR)?;
-        println!("{status}\r");
+        self.terminal.cursor_position(&Position {
+            x: area.x,
+            y: area.y.saturating_add(area.height),
+        })?;
+        self.terminal.set_bg_color(if active {
+            self.config.theme.status_bg
+        } else {
+            self.config.theme.inactive_status_bg
+        })?;
+        self.terminal.set_fg_color(self.config.theme.status_fg)?;
+        print!("{status}");
         self.terminal.reset_bg_color()?;
         self.terminal.reset_fg_color()?;
         Ok(())
     }
-    fn draw_message_bar(&mut self) {
-        let _=self.terminal.clear_current_line();
+    // 紧贴消息栏上方绘制候选列表
+    fn draw_overlay(&mut self) -> Result<(), std::io::Error> {
+        let bottom = (self.terminal.size().height as usize).saturating_add(1);
+        let width = self.terminal.size().width as usize;
+        let count = self.overlay.len().min(bottom);
+        for (index, item) in self.overlay.iter().take(count).enumerate() {
+            self.terminal.cursor_position(&Position {
+                x: 0,
+                y: bottom.saturating_sub(count).saturating_add(index),
+            })?;
+            self.terminal.clear_current_line()?;
+            let selected = self.overlay_selected == Some(index);
+            if selected {
+                self.terminal.set_inverted()?;
+            }
+            print!("{}", item.chars().take(width).collect::<String>());
+            if selected {
+                self.terminal.reset_inverted()?;
+            }
+        }
+        Ok(())
+    }
+    fn draw_message_bar(&mut self) -> Result<(), std::io::Error> {
+        self.terminal.cursor_position(&Position {
+            x: 0,
+            y: (self.terminal.size().height as usize).saturating_add(1),
+        })?;
+        self.terminal.clear_current_line()?;
         let message = &self.status_message;
         if Instant::now() - message.time < Duration::new(5, 0) {
             let mut text = message.text.clone();
             text.truncate(self.terminal.size().width as usize);
             print!("{text}");
         }
+        Ok(())
     }
     fn draw_welcome_message(&self) {
         let mut welcome_message = format!("Hecto editor -- version {VERSION}");
-        let width = self.terminal.size().width as usize;
+        let width = self.area().width;
         let len = welcome_message.len();
         let padding = width.saturating_sub(len) / 2;
         let spaces = " ".repeat(padding.saturating_sub(1));
         welcome_message = format!("~{spaces}{welcome_message}");
         welcome_message.truncate(width);
-        println!("{welcome_message}\r");
+        print!("{welcome_message}");
     }
 
-    pub fn draw_row(&self, row: &Row) {
-        let width = self.terminal.size().width as usize;
-        let start = self.offset.x;
-        let end = self.offset.x + width;
-        let row = row.render(start, end);
-        println!("{row}\r");
+    // 折行的后续视觉行不显示行号
+    fn draw_gutter(&mut self, index: usize, continued: bool) -> Result<(), std::io::Error> {
+        let width = self.gutter_width();
+        if width == 0 {
+            return Ok(());
+        }
+        if continued {
+            print!("{}", " ".repeat(width));
+            return Ok(());
+        }
+        let current = self.cursor_position.y;
+        let number = match self.line_numbers {
+            LineNumbers::Relative if index != current => index.abs_diff(current),
+            _ => index.saturating_add(1),
+        };
+        self.terminal.set_fg_color(if index == current {
+            self.config.theme.current_line_number
+        } else {
+            self.config.theme.gutter_fg
+        })?;
+        print!("{number:>0$} ", width - 1);
+        self.terminal.reset_fg_color()?;
+        Ok(())
+    }
+    // 绘制第 index 行落在显示列区间 columns 内的部分
+    pub fn draw_row(&mut self, index: usize, columns: Range<usize>) -> Result<(), std::io::Error> {
+        let width = columns.len();
+        let tab_width = self.tab_width;
+        let Some(row) = self.document.highlighted_row(index) else {
+            return Ok(());
+        };
+        // 可见区间按显示列截取，跨过左边缘的宽字符用空格补齐
+        let left = columns.start;
+        let mut start = row.index_at_column(left, tab_width);
+        let start_column = row.column(start, tab_width);
+        if start < row.len() && start_column < left {
+            let visible = start_column
+                .saturating_add(row.width(start, tab_width))
+                .saturating_sub(left);
+            print!("{}", " ".repeat(visible.min(width)));
+            start = start.saturating_add(1);
+        }
+        let end = row
+            .index_at_column(left.saturating_add(width), tab_width)
+            .max(start);
+        // 行内需要高亮的区间
+        let mut highlights = Vec::new();
+        if let Some((position, len)) = &self.highlighted_match {
+            if position.y == index {
+                highlights.push((position.x, position.x.saturating_add(*len), Highlight::Match));
+            }
+        }
+        if let Some((selection_start, selection_end)) = self.selection() {
+            if (selection_start.y..=selection_end.y).contains(&index) {
+                let from = if selection_start.y == index { selection_start.x } else { 0 };
+                let to = if selection_end.y == index { selection_end.x } else { row.len() };
+                highlights.push((from, to, Highlight::Selection));
+            }
+        }
+        // 样式相同的相邻字位一起输出
+        let mut segment_start = start;
+        let mut current = None;
+        for x in start..=end.max(start) {
+            let style = (x < end).then(|| {
+                let overlay = highlights
+                    .iter()
+                    .find(|(from, to, _)| (*from..*to).contains(&x))
+                    .map(|(_, _, highlight)| *highlight);
+                (row.highlighting(x), overlay)
+            });
+            if style != current {
+                if let Some((hl_type, overlay)) = current {
+                    self.draw_segment(&row.render(segment_start, x, tab_width), hl_type, overlay)?;
+                }
+                segment_start = x;
+                current = style;
+            }
+        }
+        Ok(())
+    }
+    fn draw_segment(
+        &mut self,
+        text: &str,
+        hl_type: highlighting::Type,
+        overlay: Option<Highlight>,
+    ) -> Result<(), std::io::Error> {
+        if hl_type != highlighting::Type::None {
+            self.terminal.set_fg_color(self.config.theme.syntax(hl_type))?;
+        }
+        match overlay {
+            Some(Highlight::Match) => self.terminal.set_bg_color(self.config.theme.match_bg)?,
+            Some(Highlight::Selection) => self.terminal.set_inverted()?,
+            None => (),
+        }
+        print!("{text}");
+        if overlay == Some(Highlight::Selection) {
+            self.terminal.reset_inverted()?;
+        }
+        self.terminal.reset_fg_color()?;
+        Ok(())
+    }
+    // 依次绘制各窗口及其状态栏，再画竖直分隔线
+    fn draw_windows(&mut self) -> Result<(), std::io::Error> {
+        let (windows, separators) = self.arrange();
+        for (index, _) in windows {
+            if index == self.current_window {
+                self.draw_rows()?;
+                self.draw_status_bar(true)?;
+            } else {
+                self.in_window(index, |editor| {
+                    editor.draw_rows()?;
+                    editor.draw_status_bar(false)
+                })?;
+            }
+        }
+        for separator in separators {
+            for y in separator.y..separator.y.saturating_add(separator.height) {
+                self.terminal.cursor_position(&Position { x: separator.x, y })?;
+                print!("│");
+            }
+        }
+        Ok(())
     }
     fn draw_rows(&mut self) -> Result<(), std::io::Error> {
-        let height = self.terminal.size().height;
+        let area = self.area();
+        let height = area.height;
+        let mut lines = self.screen_lines().into_iter();
         for terminal_row in 0..height {
-            self.terminal.clear_current_line()?;
-            if let Some(row) = self.document.row(terminal_row as usize + self.offset.y) {
-                self.draw_row(row);
+            // 只清除本窗口内的部分
+            let start = Position {
+                x: area.x,
+                y: area.y.saturating_add(terminal_row),
+            };
+            self.terminal.cursor_position(&start)?;
+            print!("{}", " ".repeat(area.width));
+            self.terminal.cursor_position(&start)?;
+            if let Some((index, line, columns)) = lines.next() {
+                self.draw_gutter(index, line > 0)?;
+                self.draw_row(index, columns)?;
             } else if self.document.is_empty() && terminal_row == height / 3 {
                 self.draw_welcome_message();
             } else {
                 // 空行前导~
-                println!("~ \r");
+                print!("~");
             }
         }
         Ok(())
@@ -0,0 +1,297 @@
+use std::{
+    fmt::{self, Write as _},
+    io::{self, Write},
+};
+
+use chardetng::EncodingDetector;
+use encoding_rs::{DecoderResult, Encoding, EncoderResult, UTF_16BE, UTF_16LE, UTF_8};
+
+// 文件的字符编码以及是否带 BOM
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct FileEncoding {
+    encoding: &'static Encoding,
+    bom: bool,
+}
+
+impl Default for FileEncoding {
+    fn default() -> Self {
+        Self {
+            encoding: UTF_8,
+            bom: false,
+        }
+    }
+}
+
+impl fmt::Display for FileEncoding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.encoding.name())?;
+        if self.bom && self.encoding == UTF_8 {
+            f.write_str(" BOM")?;
+        }
+        Ok(())
+    }
+}
+
+impl FileEncoding {
+    // 按名称查找编码，如 gbk、shift_jis、latin1、utf-16le，utf-8-bom 表示带 BOM 的 UTF-8
+    pub fn for_label(label: &str) -> Option<Self> {
+        let label = label.trim().to_ascii_lowercase();
+        let (label, bom) = match label.strip_suffix("-bom") {
+            Some(label) => (label, true),
+            None => (label.as_str(), false),
+        };
+        let encoding = Encoding::for_label(label.as_bytes())?;
+        // UTF-16 文件总是带 BOM 写出，否则无法再被识别
+        let bom = bom || encoding == UTF_16LE || encoding == UTF_16BE;
+        Some(Self { encoding, bom })
+    }
+    // 先看 BOM 与没有 BOM 的 UTF-16，再检查是否为合法 UTF-8，最后由 chardetng 猜测
+    // 其余含有 \0 的文件视为二进制，按 UTF-8 解码
+    pub fn detect(bytes: &[u8]) -> Self {
+        if let Some((encoding, _)) = Encoding::for_bom(bytes) {
+            return Self {
+                encoding,
+                bom: true,
+            };
+        }
+        if let Some(encoding) = detect_utf16(bytes) {
+            return encoding;
+        }
+        if std::str::from_utf8(bytes).is_ok() || bytes.contains(&0) {
+            return Self::default();
+        }
+        let mut detector = EncodingDetector::new();
+        detector.feed(bytes, true);
+        Self {
+            encoding: detector.guess(None, false),
+            bom: false,
+        }
+    }
+    pub fn is_utf8(&self) -> bool {
+        self.encoding == UTF_8 && !self.bom
+    }
+    // 解码为字符串，无法解码的字节显示为 \xNN，此时第二项为 false
+    pub fn decode(&self, bytes: &[u8]) -> (String, bool) {
+        let bytes = match Encoding::for_bom(bytes) {
+            Some((encoding, length)) if encoding == self.encoding => &bytes[length..],
+            _ => bytes,
+        };
+        let mut decoder = self.encoding.new_decoder_without_bom_handling();
+        let mut text = String::new();
+        let mut lossless = true;
+        let mut read = 0;
+        loop {
+            let remaining = bytes.len() - read;
+            text.reserve(
+                decoder
+                    .max_utf8_buffer_length_without_replacement(remaining)
+                    .unwrap_or(remaining),
+            );
+            let (result, consumed) =
+                decoder.decode_to_string_without_replacement(&bytes[read..], &mut text, true);
+            read += consumed;
+            match result {
+                DecoderResult::InputEmpty => break,
+                DecoderResult::OutputFull => (),
+                DecoderResult::Malformed(length, pending) => {
+                    lossless = false;
+                    let end = read - usize::from(pending);
+                    for byte in &bytes[end.saturating_sub(usize::from(length))..end] {
+                        let _ = write!(text, "\\x{byte:02X}");
+                    }
+                }
+            }
+        }
+        if !lossless {
+            // 只读视图里控制字符同样转义，避免直接输出到终端
+            text = text
+                .chars()
+                .map(|c| match c {
+                    '\t' | '\n' | '\r' => c.to_string(),
+                    c if c.is_control() => format!("\\u{{{:02X}}}", u32::from(c)),
+                    c => c.to_string(),
+                })
+                .collect();
+        }
+        (text, lossless)
+    }
+    // 按本编码写出文本的编码器，先写入 BOM
+    pub fn encoder<'a>(&self, writer: &'a mut dyn Write) -> Result<Encoder<'a>, io::Error> {
+        if self.bom {
+            writer.write_all(match self.encoding {
+                encoding if encoding == UTF_16LE => b"\xFF\xFE",
+                encoding if encoding == UTF_16BE => b"\xFE\xFF",
+                _ => b"\xEF\xBB\xBF",
+            })?;
+        }
+        // encoding_rs 不支持编码为 UTF-16，UTF-8 与 UTF-16 直接写出
+        let encoder = (self.encoding != UTF_8 && self.encoding != UTF_16LE && self.encoding != UTF_16BE)
+            .then(|| self.encoding.new_encoder());
+        Ok(Encoder {
+            encoding: self.encoding,
+            encoder,
+            writer,
+        })
+    }
+}
+
+// 没有 BOM 的 UTF-16 文本：奇数或偶数下标中至少四成是 \0，另一侧的 \0 不到其四分之一，
+// 且能解码为不含控制字符的文本
+// 以 ASCII 为主的 UTF-16LE 文本每个高位字节都是 \0
+pub fn detect_utf16(bytes: &[u8]) -> Option<FileEncoding> {
+    if bytes.len() < 2 || !bytes.len().is_multiple_of(2) {
+        return None;
+    }
+    let units = bytes.len() / 2;
+    let zeros = |parity: usize| bytes.iter().skip(parity).step_by(2).filter(|byte| **byte == 0).count();
+    let (even, odd) = (zeros(0), zeros(1));
+    let encoding = if odd * 10 >= units * 4 && even * 4 <= odd {
+        UTF_16LE
+    } else if even * 10 >= units * 4 && odd * 4 <= even {
+        UTF_16BE
+    } else {
+        return None;
+    };
+    let text = encoding.decode_without_bom_handling_and_without_replacement(bytes)?;
+    if text.chars().any(|c| c.is_control() && !matches!(c, '\t' | '\n' | '\r' | '\x0c')) {
+        return None;
+    }
+    Some(FileEncoding {
+        encoding,
+        bom: false,
+    })
+}
+
+pub struct Encoder<'a> {
+    encoding: &'static Encoding,
+    encoder: Option<encoding_rs::Encoder>,
+    writer: &'a mut dyn Write,
+}
+
+impl Encoder<'_> {
+    // 编码并写出一段文本，遇到本编码无法表示的字符时报错
+    pub fn write_str(&mut self, text: &str) -> Result<(), io::Error> {
+        self.encode(text, false)
+    }
+    // 写出编码器中剩余的状态
+    pub fn finish(mut self) -> Result<(), io::Error> {
+        self.encode("", true)
+    }
+    fn encode(&mut self, mut text: &str, last: bool) -> Result<(), io::Error> {
+        let Some(encoder) = &mut self.encoder else {
+            return match self.encoding {
+                encoding if encoding == UTF_16LE => {
+                    let bytes: Vec<u8> = text.encode_utf16().flat_map(u16::to_le_bytes).collect();
+                    self.writer.write_all(&bytes)
+                }
+                encoding if encoding == UTF_16BE => {
+                    let bytes: Vec<u8> = text.encode_utf16().flat_map(u16::to_be_bytes).collect();
+                    self.writer.write_all(&bytes)
+                }
+                _ => self.writer.write_all(text.as_bytes()),
+            };
+        };
+        let mut buffer = [0; 4096];
+        loop {
+            let (result, read, written) =
+                encoder.encode_from_utf8_without_replacement(text, &mut buffer, last);
+            self.writer.write_all(&buffer[..written])?;
+            text = &text[read..];
+            match result {
+                EncoderResult::InputEmpty => return Ok(()),
+                EncoderResult::OutputFull => (),
+                EncoderResult::Unmappable(c) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("{c:?} cannot be encoded as {}", self.encoding.name()),
+                    ));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn utf16le(text: &str) -> Vec<u8> {
+        text.encode_utf16().flat_map(u16::to_le_bytes).collect()
+    }
+    fn utf16be(text: &str) -> Vec<u8> {
+        text.encode_utf16().flat_map(u16::to_be_bytes).collect()
+    }
+    fn encode(encoding: FileEncoding, text: &str) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        let mut encoder = encoding.encoder(&mut bytes).unwrap();
+        encoder.write_str(text).unwrap();
+        encoder.finish().unwrap();
+        bytes
+    }
+
+    #[test]
+    fn detects_utf16_without_bom() {
+        let text = "fn main() {\n    println!(\"héllo 世界\");\n}\n";
+        let encoding = FileEncoding::detect(&utf16le(text));
+        assert_eq!(encoding.to_string(), "UTF-16LE");
+        assert!(!encoding.bom);
+        let encoding = FileEncoding::detect(&utf16be(text));
+        assert_eq!(encoding.to_string(), "UTF-16BE");
+        assert!(!encoding.bom);
+        // 不会被当作二进制文件以十六进制打开
+        assert!(!crate::hex::is_binary(&utf16le(text)));
+    }
+
+    #[test]
+    fn binary_data_is_not_utf16() {
+        // 小整数数组的 \0 也集中在奇数下标，但解码后是控制字符
+        let bytes: Vec<u8> = (0..64u16).flat_map(u16::to_le_bytes).collect();
+        assert!(detect_utf16(&bytes).is_none());
+        assert!(detect_utf16(b"ELF\0\x02\x01\x01\0\0\0").is_none());
+        assert!(detect_utf16(b"a\0b").is_none());
+        assert!(FileEncoding::detect(b"plain ascii").is_utf8());
+    }
+
+    #[test]
+    fn utf16_round_trips_with_and_without_bom() {
+        let text = "a\tb\r\n😀 ü\n";
+        for bytes in [
+            utf16le(text),
+            utf16be(text),
+            [&b"\xFF\xFE"[..], &utf16le(text)].concat(),
+            [&b"\xFE\xFF"[..], &utf16be(text)].concat(),
+        ] {
+            let encoding = FileEncoding::detect(&bytes);
+            let (decoded, lossless) = encoding.decode(&bytes);
+            assert!(lossless);
+            assert_eq!(decoded, text);
+            assert_eq!(encode(encoding, &decoded), bytes);
+        }
+    }
+
+    #[test]
+    fn legacy_encodings_round_trip() {
+        let encoding = FileEncoding::for_label("gbk").unwrap();
+        let bytes = encode(encoding, "中文文本");
+        let (decoded, lossless) = encoding.decode(&bytes);
+        assert!(lossless);
+        assert_eq!(decoded, "中文文本");
+        let mut bytes = Vec::new();
+        let mut encoder = FileEncoding::for_label("latin1").unwrap().encoder(&mut bytes).unwrap();
+        assert!(encoder.write_str("中").is_err());
+    }
+
+    #[test]
+    fn invalid_bytes_are_escaped_and_lossy() {
+        let (decoded, lossless) = FileEncoding::default().decode(b"a\xFFb");
+        assert!(!lossless);
+        assert_eq!(decoded, "a\\xFFb");
+    }
+
+    #[test]
+    fn labels() {
+        assert_eq!(FileEncoding::for_label("utf-8-bom").unwrap().to_string(), "UTF-8 BOM");
+        assert!(FileEncoding::for_label("utf-16le").unwrap().bom);
+        assert!(FileEncoding::for_label("nonsense").is_none());
+    }
+}
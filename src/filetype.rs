@@ -0,0 +1,167 @@
+use std::{fmt, path::Path};
+
+// 文件类型及其高亮规则
+pub struct FileType {
+    name: &'static str,
+    hl_opts: HighlightingOptions,
+}
+
+// 高亮器的语法种类
+#[derive(Default, Clone, Copy, PartialEq, Eq)]
+pub enum Syntax {
+    #[default]
+    Code,
+    Markdown,
+    Html,
+}
+
+#[derive(Default)]
+pub struct HighlightingOptions {
+    pub syntax: Syntax,
+    pub numbers: bool,
+    // 可作为字符串定界符的引号
+    pub quotes: &'static str,
+    // Rust/C 的字符字面量
+    pub characters: bool,
+    // 未闭合的普通字符串延续到下一行
+    pub multiline_strings: bool,
+    // Rust 的 raw 字符串
+    pub raw_strings: bool,
+    // Python 的三引号字符串
+    pub triple_quotes: bool,
+    pub comment: Option<&'static str>,
+    pub multiline_comment: Option<(&'static str, &'static str)>,
+    pub keywords: &'static [&'static str],
+    pub types: &'static [&'static str],
+    pub operators: &'static str,
+}
+
+impl Default for FileType {
+    fn default() -> Self {
+        Self {
+            name: "No filetype",
+            hl_opts: HighlightingOptions::default(),
+        }
+    }
+}
+
+impl fmt::Display for FileType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name)
+    }
+}
+
+impl FileType {
+    pub fn highlighting_options(&self) -> &HighlightingOptions {
+        &self.hl_opts
+    }
+    // 根据扩展名识别文件类型
+    pub fn from(file_name: &str) -> Self {
+        let extension = Path::new(file_name)
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .unwrap_or_default()
+            .to_ascii_lowercase();
+        match extension.as_str() {
+            "rs" => Self {
+                name: "Rust",
+                hl_opts: HighlightingOptions {
+                    numbers: true,
+                    quotes: "\"",
+                    characters: true,
+                    multiline_strings: true,
+                    raw_strings: true,
+                    comment: Some("//"),
+                    multiline_comment: Some(("/*", "*/")),
+                    keywords: &[
+                        "as", "async", "await", "break", "const", "continue", "crate", "dyn",
+                        "else", "enum", "extern", "false", "fn", "for", "if", "impl", "in",
+                        "let", "loop", "match", "mod", "move", "mut", "pub", "ref", "return",
+                        "self", "static", "struct", "super", "trait", "true", "type", "unsafe",
+                        "use", "where", "while",
+                    ],
+                    types: &[
+                        "bool", "char", "i8", "i16", "i32", "i64", "i128", "isize", "u8", "u16",
+                        "u32", "u64", "u128", "usize", "f32", "f64", "str", "String", "Vec",
+                        "Option", "Result", "Box", "Self", "Some", "None", "Ok", "Err",
+                    ],
+                    operators: "+-*/%=<>!&|^?:;,.",
+                    ..HighlightingOptions::default()
+                },
+            },
+            "c" | "h" => Self {
+                name: "C",
+                hl_opts: HighlightingOptions {
+                    numbers: true,
+                    quotes: "\"",
+                    characters: true,
+                    comment: Some("//"),
+                    multiline_comment: Some(("/*", "*/")),
+                    keywords: &[
+                        "break", "case", "const", "continue", "default", "do", "else", "enum",
+                        "extern", "for", "goto", "if", "inline", "register", "restrict",
+                        "return", "sizeof", "static", "struct", "switch", "typedef", "union",
+                        "volatile", "while", "#include", "#define", "#ifdef", "#ifndef", "#if",
+                        "#else", "#endif", "NULL",
+                    ],
+                    types: &[
+                        "auto", "char", "double", "float", "int", "long", "short", "signed",
+                        "unsigned", "void", "size_t", "bool", "int8_t", "int16_t", "int32_t",
+                        "int64_t", "uint8_t", "uint16_t", "uint32_t", "uint64_t",
+                    ],
+                    operators: "+-*/%=<>!&|^?:;,.~",
+                    ..HighlightingOptions::default()
+                },
+            },
+            "py" => Self {
+                name: "Python",
+                hl_opts: HighlightingOptions {
+                    numbers: true,
+                    quotes: "\"'",
+                    triple_quotes: true,
+                    comment: Some("#"),
+                    keywords: &[
+                        "and", "as", "assert", "async", "await", "break", "class", "continue",
+                        "def", "del", "elif", "else", "except", "False", "finally", "for",
+                        "from", "global", "if", "import", "in", "is", "lambda", "None",
+                        "nonlocal", "not", "or", "pass", "raise", "return", "True", "try",
+                        "while", "with", "yield",
+                    ],
+                    types: &[
+                        "bool", "bytes", "dict", "float", "int", "list", "object", "set", "str",
+                        "tuple", "self", "cls",
+                    ],
+                    operators: "+-*/%=<>!&|^~:;,.@",
+                    ..HighlightingOptions::default()
+                },
+            },
+            "json" => Self {
+                name: "JSON",
+                hl_opts: HighlightingOptions {
+                    numbers: true,
+                    quotes: "\"",
+                    keywords: &["true", "false", "null"],
+                    operators: "{}[]:,",
+                    ..HighlightingOptions::default()
+                },
+            },
+            "md" | "markdown" => Self {
+                name: "Markdown",
+                hl_opts: HighlightingOptions {
+                    syntax: Syntax::Markdown,
+                    ..HighlightingOptions::default()
+                },
+            },
+            "html" | "htm" => Self {
+                name: "HTML",
+                hl_opts: HighlightingOptions {
+                    syntax: Syntax::Html,
+                    quotes: "\"'",
+                    multiline_comment: Some(("<!--", "-->")),
+                    ..HighlightingOptions::default()
+                },
+            },
+            _ => Self::default(),
+        }
+    }
+}
@@ -0,0 +1,146 @@
+use std::{fs, path::Path};
+
+use ignore::WalkBuilder;
+
+// 当前目录下的所有文件，遵守 .gitignore 并跳过隐藏文件
+pub fn files(root: &Path) -> Vec<String> {
+    let mut files: Vec<String> = WalkBuilder::new(root)
+        .require_git(false)
+        .build()
+        .filter_map(Result::ok)
+        .filter(|entry| entry.file_type().is_some_and(|file_type| file_type.is_file()))
+        .filter_map(|entry| {
+            let path = entry.path().strip_prefix(root).unwrap_or(entry.path());
+            path.to_str().map(str::to_string)
+        })
+        .collect();
+    files.sort();
+    files
+}
+
+// 模糊匹配打分，pattern 的字符须按顺序出现在 candidate 中，不匹配时返回 None
+pub fn score(pattern: &str, candidate: &str) -> Option<i64> {
+    let characters: Vec<char> = candidate.chars().collect();
+    // 文件名部分的起点，匹配在文件名里加分
+    let file_name_start = candidate
+        .rfind('/')
+        .map_or(0, |index| candidate[..=index].chars().count());
+    let mut score: i64 = 0;
+    let mut from = 0;
+    let mut previous = None;
+    for expected in pattern.chars() {
+        let found = (from..characters.len())
+            .find(|index| characters[*index].to_lowercase().eq(expected.to_lowercase()))?;
+        score += 1;
+        if found > 0 && previous == Some(found - 1) {
+            score += 5;
+        }
+        if found == 0 || matches!(characters[found - 1], '/' | '_' | '-' | '.' | ' ') {
+            score += 3;
+        }
+        if found >= file_name_start {
+            score += 2;
+        }
+        previous = Some(found);
+        from = found + 1;
+    }
+    // 同分时较短的路径优先
+    Some(score * 1000 - characters.len() as i64)
+}
+
+// 按得分从高到低排列匹配的文件
+pub fn rank(files: &[String], pattern: &str) -> Vec<String> {
+    let mut matches: Vec<(i64, &String)> = files
+        .iter()
+        .filter_map(|file| Some((score(pattern, file)?, file)))
+        .collect();
+    matches.sort_by(|(a, a_file), (b, b_file)| b.cmp(a).then_with(|| a_file.cmp(b_file)));
+    matches.into_iter().map(|(_, file)| file.clone()).collect()
+}
+
+// 补全输入的路径，返回所有候选；唯一候选或公共前缀直接写回 input
+pub fn complete(input: &mut String) -> Vec<String> {
+    let (directory, partial) = match input.rfind('/') {
+        Some(index) => input.split_at(index + 1),
+        None => ("", input.as_str()),
+    };
+    let Ok(entries) = fs::read_dir(if directory.is_empty() { "." } else { directory }) else {
+        return Vec::new();
+    };
+    let mut candidates: Vec<String> = entries
+        .filter_map(Result::ok)
+        .filter_map(|entry| {
+            let mut name = entry.file_name().into_string().ok()?;
+            // 只有显式输入 . 时才补全隐藏文件
+            if !name.starts_with(partial) || (name.starts_with('.') && !partial.starts_with('.')) {
+                return None;
+            }
+            if entry.file_type().is_ok_and(|file_type| file_type.is_dir()) {
+                name.push('/');
+            }
+            Some(name)
+        })
+        .collect();
+    candidates.sort();
+    let Some(mut prefix) = candidates.first().cloned() else {
+        return candidates;
+    };
+    for candidate in &candidates {
+        while !candidate.starts_with(prefix.as_str()) {
+            prefix.pop();
+        }
+    }
+    *input = format!("{directory}{prefix}");
+    candidates
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn score_requires_characters_in_order() {
+        assert!(score("abc", "a/b/c.rs").is_some());
+        assert!(score("ABC", "a_b_c").is_some());
+        assert!(score("cba", "abc").is_none());
+        assert!(score("abcd", "abc").is_none());
+        assert_eq!(score("", "abc"), Some(-3));
+    }
+
+    #[test]
+    fn score_prefers_consecutive_boundary_and_file_name_matches() {
+        // 连续匹配
+        assert!(score("edit", "src/editor.rs") > score("edit", "src/e_d_i_t.rs"));
+        // 单词开头
+        assert!(score("r", "lib/row.rs") > score("r", "lib/err.rs"));
+        // 文件名部分
+        assert!(score("row", "row/lib.rs") < score("row", "lib/row.rs"));
+        // 同分时路径较短的优先
+        assert!(score("main", "main.rs") > score("main", "main.rs.bak"));
+    }
+
+    #[test]
+    fn rank_sorts_by_score_then_name() {
+        let files: Vec<String> = ["src/editor.rs", "src/document.rs", "benches/typing.rs", "src/doc.rs"]
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+        assert_eq!(rank(&files, "doc"), ["src/doc.rs", "src/document.rs"]);
+        assert_eq!(rank(&files, "xyz"), Vec::<String>::new());
+        assert_eq!(rank(&files, "").len(), files.len());
+    }
+
+    #[test]
+    fn files_respects_gitignore_and_skips_hidden() {
+        let root = std::env::temp_dir().join(format!("mini-editor-{}-finder", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join("src")).unwrap();
+        fs::create_dir_all(root.join("target")).unwrap();
+        for file in ["src/main.rs", "target/out.bin", ".hidden", "README"] {
+            fs::write(root.join(file), "").unwrap();
+        }
+        fs::write(root.join(".gitignore"), "target/\n").unwrap();
+        assert_eq!(files(&root), ["README", "src/main.rs"]);
+        fs::remove_dir_all(root).unwrap();
+    }
+}
@@ -0,0 +1,122 @@
+use std::fmt::Write;
+
+use encoding_rs::Encoding;
+
+use crate::encoding;
+
+// 每行显示的字节数
+pub const BYTES_PER_LINE: usize = 16;
+// 行首偏移量及其后空格所占的列数
+const OFFSET_WIDTH: usize = 10;
+
+// 含有 \0 且既没有 BOM 也不像 UTF-16 文本的文件按二进制文件处理
+pub fn is_binary(bytes: &[u8]) -> bool {
+    bytes.contains(&0) && Encoding::for_bom(bytes).is_none() && encoding::detect_utf16(bytes).is_none()
+}
+
+// 整个文件的十六进制转储，每行以 \n 结尾
+pub fn dump(bytes: &[u8]) -> String {
+    let lines = bytes.len().div_ceil(BYTES_PER_LINE).max(1);
+    let mut text = String::new();
+    for y in 0..lines {
+        text.push_str(&line(bytes, y));
+        text.push('\n');
+    }
+    text
+}
+
+// 第 y 行：偏移量、十六进制字节与 ASCII 列
+pub fn line(bytes: &[u8], y: usize) -> String {
+    let start = y.saturating_mul(BYTES_PER_LINE).min(bytes.len());
+    let end = start.saturating_add(BYTES_PER_LINE).min(bytes.len());
+    let mut text = format!("{start:08x}  ");
+    for index in 0..BYTES_PER_LINE {
+        if start + index < end {
+            let _ = write!(text, "{:02x} ", bytes[start + index]);
+        } else {
+            text.push_str("   ");
+        }
+        // 前后八个字节之间多空一列
+        if index == BYTES_PER_LINE / 2 - 1 {
+            text.push(' ');
+        }
+    }
+    text.push('|');
+    for byte in &bytes[start..end] {
+        text.push(if byte.is_ascii_graphic() || *byte == b' ' {
+            char::from(*byte)
+        } else {
+            '.'
+        });
+    }
+    text.push('|');
+    text
+}
+
+// 行内第 nibble 个半字节所在的列
+pub fn nibble_column(nibble: usize) -> usize {
+    let byte = nibble / 2;
+    OFFSET_WIDTH + byte * 3 + usize::from(byte >= BYTES_PER_LINE / 2) + nibble % 2
+}
+
+// column 处或其左侧最近的半字节，count 为该行的字节数
+pub fn nibble_at(column: usize, count: usize) -> Option<usize> {
+    let nibbles = count.checked_mul(2)?.checked_sub(1)?;
+    Some(
+        (0..=nibbles)
+            .rev()
+            .find(|nibble| nibble_column(*nibble) <= column)
+            .unwrap_or(0),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn is_binary_needs_nul_without_bom() {
+        assert!(is_binary(b"\x7fELF\x02\x01\x01\x00\x00\x00\x00"));
+        assert!(!is_binary(b"plain text"));
+        assert!(!is_binary(b"\xFF\xFEa\x00b\x00"));
+    }
+
+    #[test]
+    fn line_shows_offset_bytes_and_ascii() {
+        let bytes: Vec<u8> = (0x41..0x41 + 18).collect();
+        assert_eq!(
+            line(&bytes, 0),
+            "00000000  41 42 43 44 45 46 47 48  49 4a 4b 4c 4d 4e 4f 50 |ABCDEFGHIJKLMNOP|"
+        );
+        assert_eq!(
+            line(&bytes, 1),
+            "00000010  51 52                                            |QR|"
+        );
+        assert_eq!(line(b"\x00 ~", 0).rsplit('|').nth(1), Some(". ~"));
+        assert_eq!(dump(b"").lines().count(), 1);
+        assert_eq!(dump(&bytes).lines().count(), 2);
+    }
+
+    #[test]
+    fn nibble_columns_match_the_line_layout() {
+        let text = line(&[0xab; BYTES_PER_LINE], 0);
+        for nibble in 0..BYTES_PER_LINE * 2 {
+            let expected = if nibble % 2 == 0 { 'a' } else { 'b' };
+            assert_eq!(text.chars().nth(nibble_column(nibble)), Some(expected));
+        }
+    }
+
+    #[test]
+    fn nibble_at_snaps_left_and_clamps_to_the_row() {
+        assert_eq!(nibble_at(0, 16), Some(0));
+        assert_eq!(nibble_at(nibble_column(5), 16), Some(5));
+        // 字节之间的空格归左侧的半字节
+        assert_eq!(nibble_at(nibble_column(5) + 1, 16), Some(5));
+        // 中间的分隔列
+        assert_eq!(nibble_at(nibble_column(15) + 2, 16), Some(15));
+        // ASCII 列与不足一行的末尾落在最后一个半字节
+        assert_eq!(nibble_at(200, 16), Some(31));
+        assert_eq!(nibble_at(200, 3), Some(5));
+        assert_eq!(nibble_at(5, 0), None);
+    }
+}
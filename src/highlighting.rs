@@ -0,0 +1,542 @@
+use crossterm::style::Color;
+
+use crate::filetype::{HighlightingOptions, Syntax};
+
+// 字位的高亮类型
+#[derive(Default, PartialEq, Eq, Clone, Copy)]
+pub enum Type {
+    #[default]
+    None,
+    Number,
+    String,
+    Comment,
+    Keyword,
+    // 内置类型
+    Builtin,
+    Operator,
+}
+
+impl Type {
+    pub fn to_color(self) -> Color {
+        match self {
+            Type::Number => Color::Rgb {
+                r: 220,
+                g: 163,
+                b: 163,
+            },
+            Type::String => Color::Rgb {
+                r: 211,
+                g: 54,
+                b: 130,
+            },
+            Type::Comment => Color::Rgb {
+                r: 133,
+                g: 153,
+                b: 0,
+            },
+            Type::Keyword => Color::Rgb {
+                r: 181,
+                g: 137,
+                b: 0,
+            },
+            Type::Builtin => Color::Rgb {
+                r: 42,
+                g: 161,
+                b: 152,
+            },
+            Type::Operator => Color::Rgb {
+                r: 108,
+                g: 113,
+                b: 196,
+            },
+            Type::None => Color::Reset,
+        }
+    }
+}
+
+// 行尾的词法状态，决定下一行从哪里继续
+#[derive(Default, PartialEq, Eq, Clone)]
+pub enum State {
+    #[default]
+    Normal,
+    // 块注释，直到结束符
+    Comment(&'static str),
+    // 跨行字符串，raw 字符串不处理转义
+    String { close: String, raw: bool },
+    // HTML 标签内部
+    Tag,
+    // Markdown 代码块内部
+    Fence,
+}
+
+// 从上一行行尾的状态开始，为一行的字位逐个标注高亮类型，返回本行行尾的状态
+pub fn highlight(graphemes: &[&str], opts: &HighlightingOptions, state: State) -> (Vec<Type>, State) {
+    let mut lexer = Lexer {
+        graphemes,
+        opts,
+        highlighting: vec![Type::None; graphemes.len()],
+        state,
+    };
+    match opts.syntax {
+        Syntax::Code => lexer.code(),
+        Syntax::Markdown => lexer.markdown(),
+        Syntax::Html => lexer.html(),
+    }
+    (lexer.highlighting, lexer.state)
+}
+
+struct Lexer<'a> {
+    graphemes: &'a [&'a str],
+    opts: &'a HighlightingOptions,
+    highlighting: Vec<Type>,
+    state: State,
+}
+
+impl<'a> Lexer<'a> {
+    fn len(&self) -> usize {
+        self.graphemes.len()
+    }
+    fn get(&self, index: usize) -> &'a str {
+        self.graphemes.get(index).copied().unwrap_or_default()
+    }
+    // pattern 是否从 index 处开始
+    fn matches_at(&self, index: usize, pattern: &str) -> bool {
+        let mut buffer = [0; 4];
+        pattern
+            .chars()
+            .enumerate()
+            .all(|(offset, c)| self.get(index + offset) == c.encode_utf8(&mut buffer))
+    }
+    fn find(&self, from: usize, pattern: &str) -> Option<usize> {
+        (from..self.len()).find(|index| self.matches_at(*index, pattern))
+    }
+    fn fill(&mut self, start: usize, end: usize, hl_type: Type) {
+        let end = end.min(self.len());
+        for highlighting in &mut self.highlighting[start.min(end)..end] {
+            *highlighting = hl_type;
+        }
+    }
+    fn word_end(&self, start: usize) -> usize {
+        (start..self.len())
+            .find(|index| is_separator(self.get(*index)))
+            .unwrap_or(self.len())
+    }
+    fn comment(&mut self, index: usize) -> Option<usize> {
+        if let Some(prefix) = self.opts.comment {
+            if self.matches_at(index, prefix) {
+                self.fill(index, self.len(), Type::Comment);
+                return Some(self.len());
+            }
+        }
+        let (open, close) = self.opts.multiline_comment?;
+        if !self.matches_at(index, open) {
+            return None;
+        }
+        Some(self.comment_body(index, index + open.chars().count(), close))
+    }
+    // 注释内容直到 close，行内没有结束时进入跨行注释状态
+    fn comment_body(&mut self, start: usize, from: usize, close: &'static str) -> usize {
+        let end = match self.find(from, close) {
+            Some(end) => end + close.chars().count(),
+            None => {
+                self.state = State::Comment(close);
+                self.len()
+            }
+        };
+        self.fill(start, end, Type::Comment);
+        end
+    }
+    fn string(&mut self, index: usize) -> Option<usize> {
+        // Rust 的 r"..." 与 r#"..."#
+        if self.opts.raw_strings
+            && self.get(index) == "r"
+            && (index == 0 || is_separator(self.get(index - 1)))
+        {
+            let hashes = (index + 1..self.len())
+                .find(|end| self.get(*end) != "#")
+                .unwrap_or(self.len())
+                - index
+                - 1;
+            if self.get(index + 1 + hashes) == "\"" {
+                let close = format!("\"{}", "#".repeat(hashes));
+                return Some(self.string_body(index, index + hashes + 2, &close, true, true));
+            }
+        }
+        let quote = self.get(index);
+        if quote.is_empty() || !self.opts.quotes.contains(quote) {
+            return None;
+        }
+        // Python 的三引号字符串
+        let triple = quote.repeat(3);
+        if self.opts.triple_quotes && self.matches_at(index, &triple) {
+            return Some(self.string_body(index, index + 3, &triple, false, true));
+        }
+        Some(self.string_body(
+            index,
+            index + 1,
+            quote,
+            false,
+            self.opts.multiline_strings,
+        ))
+    }
+    // 字符串内容直到 close，允许跨行的字符串在行内没有结束时进入跨行状态
+    fn string_body(&mut self, start: usize, from: usize, close: &str, raw: bool, multiline: bool) -> usize {
+        let mut end = from;
+        let closed = loop {
+            if end >= self.len() {
+                break false;
+            }
+            if !raw && self.get(end) == "\\" {
+                end += 2;
+            } else if self.matches_at(end, close) {
+                end += close.chars().count();
+                break true;
+            } else {
+                end += 1;
+            }
+        };
+        if !closed && multiline {
+            self.state = State::String {
+                close: close.to_string(),
+                raw,
+            };
+        }
+        self.fill(start, end, Type::String);
+        end.min(self.len())
+    }
+    // 接着上一行的跨行注释或字符串继续
+    fn resume(&mut self) -> usize {
+        match std::mem::take(&mut self.state) {
+            State::Comment(close) => self.comment_body(0, 0, close),
+            State::String { close, raw } => self.string_body(0, 0, &close, raw, true),
+            state => {
+                self.state = state;
+                0
+            }
+        }
+    }
+    // 'a' 或 '\n' 形式的字符字面量，与 Rust 生命周期区分
+    fn character(&mut self, index: usize) -> Option<usize> {
+        if !self.opts.characters || self.get(index) != "'" {
+            return None;
+        }
+        let close = if self.get(index + 1) == "\\" {
+            (index + 3..self.len().min(index + 12)).find(|end| self.get(*end) == "'")?
+        } else if self.get(index + 2) == "'" {
+            index + 2
+        } else {
+            return None;
+        };
+        self.fill(index, close + 1, Type::String);
+        Some(close + 1)
+    }
+    fn number(&mut self, index: usize) -> Option<usize> {
+        let starts_number = self.get(index).chars().all(|c| c.is_ascii_digit())
+            && (index == 0 || is_separator(self.get(index - 1)));
+        if !self.opts.numbers || !starts_number {
+            return None;
+        }
+        let mut end = self.word_end(index);
+        // 小数部分
+        if self.get(end) == "."
+            && self.get(end + 1).chars().next().is_some_and(|c| c.is_ascii_digit())
+        {
+            end = self.word_end(end + 1);
+        }
+        self.fill(index, end, Type::Number);
+        Some(end)
+    }
+    fn word(&mut self, index: usize) -> Option<usize> {
+        if is_separator(self.get(index)) {
+            return None;
+        }
+        let end = self.word_end(index);
+        let word: String = self.graphemes[index..end].concat();
+        // C 预处理指令等以 # 开头的关键字
+        let start = if index > 0 && self.get(index - 1) == "#" && self.opts.keywords.contains(&format!("#{word}").as_str()) {
+            index - 1
+        } else {
+            index
+        };
+        if start < index || self.opts.keywords.contains(&word.as_str()) {
+            self.fill(start, end, Type::Keyword);
+        } else if self.opts.types.contains(&word.as_str()) {
+            self.fill(start, end, Type::Builtin);
+        }
+        Some(end)
+    }
+    fn code(&mut self) {
+        let mut index = self.resume();
+        while index < self.len() {
+            if let Some(end) = self
+                .comment(index)
+                .or_else(|| self.string(index))
+                .or_else(|| self.character(index))
+                .or_else(|| self.number(index))
+                .or_else(|| self.word(index))
+            {
+                index = end;
+                continue;
+            }
+            if !self.get(index).is_empty() && self.opts.operators.contains(self.get(index)) {
+                self.highlighting[index] = Type::Operator;
+            }
+            index += 1;
+        }
+    }
+    fn markdown(&mut self) {
+        let indent = (0..self.len())
+            .find(|index| !self.get(*index).trim().is_empty())
+            .unwrap_or(self.len());
+        let fence = self.matches_at(indent, "```");
+        // 代码块内部整行按字符串显示，直到结束标记
+        if self.state == State::Fence {
+            if fence {
+                self.state = State::Normal;
+            }
+            return self.fill(0, self.len(), Type::String);
+        }
+        match self.get(indent) {
+            // 标题与代码块标记
+            "#" => return self.fill(0, self.len(), Type::Keyword),
+            "`" if fence => {
+                self.state = State::Fence;
+                return self.fill(0, self.len(), Type::String);
+            }
+            // 引用
+            ">" => return self.fill(0, self.len(), Type::Comment),
+            "-" | "*" | "+" if self.get(indent + 1) == " " => self.fill(indent, indent + 1, Type::Operator),
+            _ => {
+                let digits_end = (indent..self.len())
+                    .find(|index| !self.get(*index).chars().all(|c| c.is_ascii_digit()))
+                    .unwrap_or(self.len());
+                if digits_end > indent && self.get(digits_end) == "." && self.get(digits_end + 1) == " " {
+                    self.fill(indent, digits_end + 1, Type::Operator);
+                }
+            }
+        }
+        let mut index = indent;
+        while index < self.len() {
+            index = match self.get(index) {
+                // 行内代码
+                "`" => self.span(index, "`", Type::String),
+                // 粗体与斜体
+                marker @ ("*" | "_") => {
+                    let marker = if self.get(index + 1) == marker {
+                        marker.repeat(2)
+                    } else {
+                        marker.to_string()
+                    };
+                    self.span(index, &marker, Type::Builtin)
+                }
+                "[" => self.link(index),
+                _ => index + 1,
+            };
+        }
+    }
+    // 由 marker 包围的行内片段
+    fn span(&mut self, start: usize, marker: &str, hl_type: Type) -> usize {
+        let content = start + marker.chars().count();
+        match self.find(content, marker).filter(|end| *end > content) {
+            Some(end) => {
+                let end = end + marker.chars().count();
+                self.fill(start, end, hl_type);
+                end
+            }
+            None => start + 1,
+        }
+    }
+    // [文本](链接)
+    fn link(&mut self, start: usize) -> usize {
+        let Some(end) = self.find(start + 1, "]") else {
+            return start + 1;
+        };
+        self.fill(start, end + 1, Type::Keyword);
+        if self.get(end + 1) != "(" {
+            return end + 1;
+        }
+        match self.find(end + 2, ")") {
+            Some(close) => {
+                self.fill(end + 1, close + 1, Type::String);
+                close + 1
+            }
+            None => end + 1,
+        }
+    }
+    fn html(&mut self) {
+        let mut index = self.resume();
+        if self.state == State::Tag {
+            self.state = State::Normal;
+            index = self.attributes(0);
+        }
+        while index < self.len() {
+            if let Some(end) = self.comment(index) {
+                index = end;
+                continue;
+            }
+            match self.get(index) {
+                "<" => index = self.tag(index),
+                // 字符实体
+                "&" => {
+                    index = match self.find(index + 1, ";").filter(|end| {
+                        (index + 1..*end).all(|inner| !is_separator(self.get(inner)) || self.get(inner) == "#")
+                    }) {
+                        Some(end) => {
+                            self.fill(index, end + 1, Type::Number);
+                            end + 1
+                        }
+                        None => index + 1,
+                    }
+                }
+                _ => index += 1,
+            }
+        }
+    }
+    // 标签：标签名、属性名与属性值
+    fn tag(&mut self, start: usize) -> usize {
+        self.highlighting[start] = Type::Operator;
+        let mut index = start + 1;
+        if self.get(index) == "/" || self.get(index) == "!" {
+            self.highlighting[index] = Type::Operator;
+            index += 1;
+        }
+        let name_end = self.word_end(index);
+        self.fill(index, name_end, Type::Keyword);
+        self.attributes(name_end)
+    }
+    // 属性名与属性值直到 >，行内没有结束时进入标签状态
+    fn attributes(&mut self, from: usize) -> usize {
+        let mut index = from;
+        while index < self.len() {
+            match self.get(index) {
+                ">" => {
+                    self.highlighting[index] = Type::Operator;
+                    return index + 1;
+                }
+                "/" | "=" => {
+                    self.highlighting[index] = Type::Operator;
+                    index += 1;
+                }
+                g if g.trim().is_empty() => index += 1,
+                _ => {
+                    if let Some(end) = self.string(index) {
+                        index = end;
+                    } else {
+                        let end = (index..self.len())
+                            .find(|end| matches!(self.get(*end), "=" | ">" | "/" | " " | "\t"))
+                            .unwrap_or(self.len())
+                            .max(index + 1);
+                        self.fill(index, end, Type::Builtin);
+                        index = end;
+                    }
+                }
+            }
+        }
+        self.state = State::Tag;
+        index
+    }
+}
+
+fn is_separator(grapheme: &str) -> bool {
+    grapheme
+        .chars()
+        .all(|c| (c.is_ascii_punctuation() && c != '_') || c.is_whitespace())
+}
+
+#[cfg(test)]
+mod tests {
+    use unicode_segmentation::UnicodeSegmentation;
+
+    use super::*;
+    use crate::filetype::FileType;
+
+    // 每种类型一个字符，便于整行比较
+    fn code(hl_type: Type) -> char {
+        match hl_type {
+            Type::None => '.',
+            Type::Number => 'n',
+            Type::String => 's',
+            Type::Comment => 'c',
+            Type::Keyword => 'k',
+            Type::Builtin => 'b',
+            Type::Operator => 'o',
+        }
+    }
+
+    // 逐行高亮并把行尾状态带到下一行，返回每行的类型代码与最后的状态
+    fn highlight_lines(file_name: &str, lines: &[&str]) -> (Vec<String>, State) {
+        let file_type = FileType::from(file_name);
+        let mut state = State::Normal;
+        let codes = lines
+            .iter()
+            .map(|line| {
+                let graphemes: Vec<&str> = line.graphemes(true).collect();
+                let (types, end) = highlight(&graphemes, file_type.highlighting_options(), state.clone());
+                state = end;
+                types.into_iter().map(code).collect()
+            })
+            .collect();
+        (codes, state)
+    }
+
+    #[test]
+    fn each_filetype_highlights_its_rules() {
+        let cases = [
+            ("a.rs", "let s: String = \"a\\\"b\"; // c", "kkk..o.bbbbbb.o.sssssso.cccc"),
+            ("a.rs", "let c = 'x'; 1.5", "kkk...o.ssso.nnn"),
+            ("a.c", "int n = sizeof(x) * 10; /* c */", "bbb...o.kkkkkk....o.nno.ccccccc"),
+            ("a.c", "#include <stdio.h>", "kkkkkkkk.o.....o.o"),
+            ("a.py", "def f(self): return 'a' # c", "kkk...bbbb.o.kkkkkk.sss.ccc"),
+            ("a.json", "{\"a\": [1, true, null]}", "ossso.ono.kkkko.kkkkoo"),
+            ("a.md", "# Title", "kkkkkkk"),
+            ("a.md", "- a `b` **c** [d](e)", "o...sss.bbbbb.kkksss"),
+            ("a.md", "1. x", "oo.."),
+            ("a.md", "> quote", "ccccccc"),
+            ("a.html", "<a href=\"x\">&amp;</a>", "ok.bbbbosssonnnnnooko"),
+            ("a.html", "<!-- c -->", "cccccccccc"),
+            ("a.txt", "let x = 1;", ".........."),
+        ];
+        for (file_name, line, expected) in cases {
+            let (codes, _) = highlight_lines(file_name, &[line]);
+            assert_eq!(codes[0], expected, "{file_name}: {line}");
+        }
+    }
+
+    #[test]
+    fn block_comment_continues_across_lines() {
+        let (codes, state) = highlight_lines("a.c", &["int a; /* start", "still", "end */ int b;"]);
+        assert_eq!(codes, ["bbb..o.cccccccc", "ccccc", "cccccc.bbb..o"]);
+        assert!(state == State::Normal);
+        let (_, state) = highlight_lines("a.c", &["/* open"]);
+        assert!(state == State::Comment("*/"));
+    }
+
+    #[test]
+    fn raw_string_continues_across_lines() {
+        let (codes, state) = highlight_lines("a.rs", &["let s = r#\"a", "\"b\\", "c\"#;"]);
+        assert_eq!(codes, ["kkk...o.ssss", "sss", "ssso"]);
+        assert!(state == State::Normal);
+        let (_, state) = highlight_lines("a.rs", &["r#\"a\"", "b"]);
+        assert!(
+            state
+                == State::String {
+                    close: "\"#".to_string(),
+                    raw: true
+                }
+        );
+    }
+
+    #[test]
+    fn triple_quoted_string_continues_across_lines() {
+        let (codes, state) = highlight_lines("a.py", &["x = \"\"\"doc", "it's \"quoted\"", "end\"\"\" + 1"]);
+        assert_eq!(codes, ["..o.ssssss", "sssssssssssss", "ssssss.o.n"]);
+        assert!(state == State::Normal);
+    }
+
+    #[test]
+    fn unterminated_string_ends_with_the_line_when_not_multiline() {
+        let (codes, state) = highlight_lines("a.c", &["\"abc", "int"]);
+        assert_eq!(codes, ["ssss", "bbb"]);
+        assert!(state == State::Normal);
+    }
+}
@@ -0,0 +1,288 @@
+use unicode_segmentation::UnicodeSegmentation;
+
+use crate::Position;
+
+// 对文档的单次基本编辑
+#[derive(Clone)]
+pub enum Edit {
+    // 在行内插入文本
+    Insert { at: Position, text: String },
+    // 删除行内文本
+    Delete { at: Position, text: String },
+    // 在 at 处将一行拆成两行
+    Split { at: Position },
+    // 将 at.y 的下一行拼接到 at.y 末尾
+    Join { at: Position },
+}
+
+impl Edit {
+    pub fn inverse(&self) -> Self {
+        match self {
+            Self::Insert { at, text } => Self::Delete {
+                at: at.clone(),
+                text: text.clone(),
+            },
+            Self::Delete { at, text } => Self::Insert {
+                at: at.clone(),
+                text: text.clone(),
+            },
+            Self::Split { at } => Self::Join { at: at.clone() },
+            Self::Join { at } => Self::Split { at: at.clone() },
+        }
+    }
+    // 编辑开始处
+    pub fn start(&self) -> Position {
+        match self {
+            Self::Insert { at, .. } | Self::Delete { at, .. } | Self::Split { at } | Self::Join { at } => {
+                at.clone()
+            }
+        }
+    }
+    // 编辑完成后光标应在的位置
+    pub fn end(&self) -> Position {
+        match self {
+            Self::Insert { at, text } => Position {
+                x: at.x.saturating_add(text.graphemes(true).count()),
+                y: at.y,
+            },
+            Self::Split { at } => Position {
+                x: 0,
+                y: at.y.saturating_add(1),
+            },
+            Self::Delete { at, .. } | Self::Join { at } => at.clone(),
+        }
+    }
+    // 合并连续输入或连续删除，成功返回 true
+    fn merge(&mut self, next: &Self) -> bool {
+        match (self, next) {
+            (Self::Insert { at, text }, Self::Insert { at: next_at, text: next_text }) => {
+                if next_at.y == at.y && next_at.x == at.x.saturating_add(text.graphemes(true).count()) {
+                    text.push_str(next_text);
+                    return true;
+                }
+                false
+            }
+            (Self::Delete { at, text }, Self::Delete { at: next_at, text: next_text }) => {
+                if next_at.y != at.y {
+                    return false;
+                }
+                // Delete 键
+                if next_at.x == at.x {
+                    text.push_str(next_text);
+                    return true;
+                }
+                // 退格键
+                if next_at.x.saturating_add(next_text.graphemes(true).count()) == at.x {
+                    text.insert_str(0, next_text);
+                    at.x = next_at.x;
+                    return true;
+                }
+                false
+            }
+            _ => false,
+        }
+    }
+}
+
+// 撤销/重做历史，每一步包含若干编辑
+pub struct History {
+    undo_stack: Vec<Vec<Edit>>,
+    redo_stack: Vec<Vec<Edit>>,
+    // 保存时撤销栈的深度，None 表示已无法回到保存状态
+    saved: Option<usize>,
+    // 下一次编辑是否开启新的一步
+    sealed: bool,
+}
+
+impl Default for History {
+    fn default() -> Self {
+        Self {
+            undo_stack: Vec::new(),
+            redo_stack: Vec::new(),
+            saved: Some(0),
+            sealed: true,
+        }
+    }
+}
+
+impl History {
+    // 记录一次编辑，能合并时并入上一步
+    pub fn push(&mut self, edit: Edit) {
+        if !self.sealed {
+            if let Some(last) = self.undo_stack.last_mut().and_then(|step| step.last_mut()) {
+                if last.merge(&edit) {
+                    self.redo_stack.clear();
+                    return;
+                }
+            }
+        }
+        self.start_step();
+        self.extend(edit);
+    }
+    // 将编辑追加到当前一步，不做合并
+    pub fn extend(&mut self, edit: Edit) {
+        if self.sealed {
+            self.start_step();
+        }
+        if let Some(step) = self.undo_stack.last_mut() {
+            step.push(edit);
+        }
+        self.redo_stack.clear();
+    }
+    // 结束当前一步
+    pub fn seal(&mut self) {
+        self.sealed = true;
+    }
+    fn start_step(&mut self) {
+        if self.saved.is_some_and(|saved| saved > self.undo_stack.len()) {
+            self.saved = None;
+        }
+        self.undo_stack.push(Vec::new());
+        self.sealed = false;
+    }
+    // 返回需要依次应用的逆编辑
+    pub fn undo(&mut self) -> Option<Vec<Edit>> {
+        let step = self.undo_stack.pop()?;
+        self.sealed = true;
+        let inverse = step.iter().rev().map(Edit::inverse).collect();
+        self.redo_stack.push(step);
+        Some(inverse)
+    }
+    pub fn redo(&mut self) -> Option<Vec<Edit>> {
+        let step = self.redo_stack.pop()?;
+        self.sealed = true;
+        self.undo_stack.push(step.clone());
+        Some(step)
+    }
+    pub fn mark_saved(&mut self) {
+        self.sealed = true;
+        self.saved = Some(self.undo_stack.len());
+    }
+    pub fn is_saved(&self) -> bool {
+        self.saved == Some(self.undo_stack.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn insert(x: usize, text: &str) -> Edit {
+        Edit::Insert {
+            at: Position { x, y: 0 },
+            text: text.to_string(),
+        }
+    }
+    fn delete(x: usize, text: &str) -> Edit {
+        Edit::Delete {
+            at: Position { x, y: 0 },
+            text: text.to_string(),
+        }
+    }
+    fn texts(edits: &[Edit]) -> Vec<String> {
+        edits
+            .iter()
+            .map(|edit| match edit {
+                Edit::Insert { at, text } => format!("+{}:{text}", at.x),
+                Edit::Delete { at, text } => format!("-{}:{text}", at.x),
+                Edit::Split { at } => format!("split {}", at.x),
+                Edit::Join { at } => format!("join {}", at.x),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn consecutive_typing_merges_into_one_step() {
+        let mut history = History::default();
+        history.push(insert(0, "a"));
+        history.push(insert(1, "b"));
+        history.push(insert(2, "c"));
+        assert_eq!(texts(&history.undo().unwrap()), ["-0:abc"]);
+        assert!(history.undo().is_none());
+    }
+
+    #[test]
+    fn non_adjacent_edits_start_new_steps() {
+        let mut history = History::default();
+        history.push(insert(0, "a"));
+        history.push(insert(5, "b"));
+        assert_eq!(texts(&history.undo().unwrap()), ["-5:b"]);
+        assert_eq!(texts(&history.undo().unwrap()), ["-0:a"]);
+    }
+
+    #[test]
+    fn backspace_and_delete_merge() {
+        let mut history = History::default();
+        history.push(delete(3, "c"));
+        history.push(delete(2, "b"));
+        history.push(delete(2, "d"));
+        assert_eq!(texts(&history.undo().unwrap()), ["+2:bcd"]);
+    }
+
+    #[test]
+    fn seal_stops_merging() {
+        let mut history = History::default();
+        history.push(insert(0, "a"));
+        history.seal();
+        history.push(insert(1, "b"));
+        assert_eq!(texts(&history.undo().unwrap()), ["-1:b"]);
+        assert_eq!(texts(&history.undo().unwrap()), ["-0:a"]);
+    }
+
+    #[test]
+    fn extend_groups_edits_and_undo_reverses_them() {
+        let mut history = History::default();
+        history.extend(delete(0, "x"));
+        history.extend(insert(0, "y"));
+        history.seal();
+        assert_eq!(texts(&history.undo().unwrap()), ["-0:y", "+0:x"]);
+        assert_eq!(texts(&history.redo().unwrap()), ["-0:x", "+0:y"]);
+        assert!(history.redo().is_none());
+    }
+
+    #[test]
+    fn new_edit_clears_redo() {
+        let mut history = History::default();
+        history.push(insert(0, "a"));
+        history.undo();
+        history.push(insert(0, "b"));
+        assert!(history.redo().is_none());
+    }
+
+    #[test]
+    fn undo_back_to_saved_is_clean() {
+        let mut history = History::default();
+        history.push(insert(0, "a"));
+        history.mark_saved();
+        assert!(history.is_saved());
+        history.push(insert(1, "b"));
+        assert!(!history.is_saved());
+        history.undo();
+        assert!(history.is_saved());
+        history.undo();
+        assert!(!history.is_saved());
+        history.redo();
+        assert!(history.is_saved());
+    }
+
+    #[test]
+    fn typing_after_save_does_not_merge_into_saved_step() {
+        let mut history = History::default();
+        history.push(insert(0, "a"));
+        history.mark_saved();
+        history.push(insert(1, "b"));
+        history.undo();
+        assert!(history.is_saved());
+    }
+
+    #[test]
+    fn saved_state_is_lost_when_its_branch_is_discarded() {
+        let mut history = History::default();
+        history.push(insert(0, "a"));
+        history.mark_saved();
+        history.undo();
+        history.push(insert(0, "b"));
+        history.undo();
+        assert!(!history.is_saved());
+    }
+}
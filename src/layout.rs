@@ -0,0 +1,275 @@
+use std::mem;
+
+// 分割方向
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum SplitDirection {
+    // 上下排列
+    Horizontal,
+    // 左右排列，中间留一列分隔线
+    Vertical,
+}
+
+// 屏幕上的矩形区域
+#[derive(Clone, Copy, Default)]
+pub struct Rect {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+// 窗口布局树，叶子为窗口下标
+pub enum Layout {
+    Window(usize),
+    Split {
+        direction: SplitDirection,
+        // 前一部分所占的百分比
+        ratio: usize,
+        first: Box<Layout>,
+        second: Box<Layout>,
+    },
+}
+
+// 每个窗口至少保留一行文本和一行状态栏
+const MIN_WINDOW_HEIGHT: usize = 2;
+const MIN_WINDOW_WIDTH: usize = 1;
+
+impl Default for Layout {
+    fn default() -> Self {
+        Self::Window(0)
+    }
+}
+
+// 按比例切分长度，两部分都不小于 min
+fn split_length(length: usize, ratio: usize, min: usize) -> usize {
+    (length * ratio / 100).clamp(min.min(length), length.saturating_sub(min).max(min.min(length)))
+}
+
+impl Layout {
+    // 计算各窗口的区域与竖直分隔线的位置
+    pub fn arrange(&self, rect: Rect, windows: &mut Vec<(usize, Rect)>, separators: &mut Vec<Rect>) {
+        match self {
+            Self::Window(window) => windows.push((*window, rect)),
+            Self::Split {
+                direction: SplitDirection::Horizontal,
+                ratio,
+                first,
+                second,
+            } => {
+                let height = split_length(rect.height, *ratio, MIN_WINDOW_HEIGHT);
+                first.arrange(Rect { height, ..rect }, windows, separators);
+                second.arrange(
+                    Rect {
+                        y: rect.y.saturating_add(height),
+                        height: rect.height.saturating_sub(height),
+                        ..rect
+                    },
+                    windows,
+                    separators,
+                );
+            }
+            Self::Split {
+                direction: SplitDirection::Vertical,
+                ratio,
+                first,
+                second,
+            } => {
+                let available = rect.width.saturating_sub(1);
+                let width = split_length(available, *ratio, MIN_WINDOW_WIDTH);
+                first.arrange(Rect { width, ..rect }, windows, separators);
+                separators.push(Rect {
+                    x: rect.x.saturating_add(width),
+                    width: 1,
+                    ..rect
+                });
+                second.arrange(
+                    Rect {
+                        x: rect.x.saturating_add(width).saturating_add(1),
+                        width: available.saturating_sub(width),
+                        ..rect
+                    },
+                    windows,
+                    separators,
+                );
+            }
+        }
+    }
+    // 把 window 一分为二，新窗口在后
+    pub fn split(&mut self, window: usize, new_window: usize, direction: SplitDirection) -> bool {
+        match self {
+            Self::Window(index) if *index == window => {
+                *self = Self::Split {
+                    direction,
+                    ratio: 50,
+                    first: Box::new(Self::Window(window)),
+                    second: Box::new(Self::Window(new_window)),
+                };
+                true
+            }
+            Self::Window(_) => false,
+            Self::Split { first, second, .. } => {
+                first.split(window, new_window, direction)
+                    || second.split(window, new_window, direction)
+            }
+        }
+    }
+    // 移除 window，由兄弟节点占据其空间，之后的窗口下标减一
+    pub fn remove(&mut self, window: usize) -> bool {
+        let removed = self.remove_leaf(window);
+        if removed {
+            self.renumber(window);
+        }
+        removed
+    }
+    fn remove_leaf(&mut self, window: usize) -> bool {
+        let Self::Split { first, second, .. } = self else {
+            return false;
+        };
+        let sibling = if matches!(**first, Self::Window(index) if index == window) {
+            mem::take(second)
+        } else if matches!(**second, Self::Window(index) if index == window) {
+            mem::take(first)
+        } else {
+            return first.remove_leaf(window) || second.remove_leaf(window);
+        };
+        *self = *sibling;
+        true
+    }
+    fn renumber(&mut self, removed: usize) {
+        match self {
+            Self::Window(index) => {
+                if *index > removed {
+                    *index -= 1;
+                }
+            }
+            Self::Split { first, second, .. } => {
+                first.renumber(removed);
+                second.renumber(removed);
+            }
+        }
+    }
+    // 调整包含 window 的最近一次分割，delta 为 window 一侧增加的百分比
+    pub fn resize(&mut self, window: usize, delta: isize) -> bool {
+        let Self::Split {
+            ratio,
+            first,
+            second,
+            ..
+        } = self
+        else {
+            return false;
+        };
+        if first.resize(window, delta) || second.resize(window, delta) {
+            return true;
+        }
+        let delta = if first.contains(window) {
+            delta
+        } else if second.contains(window) {
+            -delta
+        } else {
+            return false;
+        };
+        *ratio = ratio.saturating_add_signed(delta).clamp(10, 90);
+        true
+    }
+    fn contains(&self, window: usize) -> bool {
+        match self {
+            Self::Window(index) => *index == window,
+            Self::Split { first, second, .. } => first.contains(window) || second.contains(window),
+        }
+    }
+    // 按屏幕上的先后顺序列出窗口
+    pub fn windows(&self) -> Vec<usize> {
+        match self {
+            Self::Window(index) => vec![*index],
+            Self::Split { first, second, .. } => {
+                let mut windows = first.windows();
+                windows.extend(second.windows());
+                windows
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // x, y, 宽, 高
+    type Area = [usize; 4];
+
+    fn arrange(layout: &Layout, width: usize, height: usize) -> (Vec<(usize, Area)>, Vec<Area>) {
+        let mut windows = Vec::new();
+        let mut separators = Vec::new();
+        layout.arrange(
+            Rect {
+                x: 0,
+                y: 0,
+                width,
+                height,
+            },
+            &mut windows,
+            &mut separators,
+        );
+        let rect = |rect: Rect| [rect.x, rect.y, rect.width, rect.height];
+        (
+            windows.into_iter().map(|(window, area)| (window, rect(area))).collect(),
+            separators.into_iter().map(rect).collect(),
+        )
+    }
+
+    #[test]
+    fn split_horizontally_and_vertically() {
+        let mut layout = Layout::default();
+        assert!(layout.split(0, 1, SplitDirection::Horizontal));
+        assert!(layout.split(1, 2, SplitDirection::Vertical));
+        assert!(!layout.split(5, 3, SplitDirection::Vertical));
+        assert_eq!(layout.windows(), [0, 1, 2]);
+        let (windows, separators) = arrange(&layout, 81, 20);
+        assert_eq!(
+            windows,
+            [(0, [0, 0, 81, 10]), (1, [0, 10, 40, 10]), (2, [41, 10, 40, 10])]
+        );
+        assert_eq!(separators, [[40, 10, 1, 10]]);
+    }
+
+    #[test]
+    fn small_areas_keep_minimum_sizes() {
+        let mut layout = Layout::default();
+        layout.split(0, 1, SplitDirection::Horizontal);
+        let (windows, _) = arrange(&layout, 10, 3);
+        assert_eq!(windows, [(0, [0, 0, 10, 2]), (1, [0, 2, 10, 1])]);
+        let (windows, _) = arrange(&layout, 10, 0);
+        assert_eq!(windows, [(0, [0, 0, 10, 0]), (1, [0, 0, 10, 0])]);
+    }
+
+    #[test]
+    fn remove_gives_space_to_sibling_and_renumbers() {
+        let mut layout = Layout::default();
+        layout.split(0, 1, SplitDirection::Vertical);
+        layout.split(1, 2, SplitDirection::Horizontal);
+        assert!(layout.remove(1));
+        assert_eq!(layout.windows(), [0, 1]);
+        let (windows, _) = arrange(&layout, 21, 10);
+        assert_eq!(windows, [(0, [0, 0, 10, 10]), (1, [11, 0, 10, 10])]);
+        assert!(layout.remove(0));
+        assert_eq!(layout.windows(), [0]);
+        // 最后一个窗口不能移除
+        assert!(!layout.remove(0));
+    }
+
+    #[test]
+    fn resize_adjusts_nearest_split_and_clamps() {
+        let mut layout = Layout::default();
+        assert!(!layout.resize(0, 10));
+        layout.split(0, 1, SplitDirection::Horizontal);
+        assert!(layout.resize(1, 20));
+        let (windows, _) = arrange(&layout, 10, 100);
+        assert_eq!(windows, [(0, [0, 0, 10, 30]), (1, [0, 30, 10, 70])]);
+        for _ in 0..10 {
+            layout.resize(0, 10);
+        }
+        let (windows, _) = arrange(&layout, 10, 100);
+        assert_eq!(windows, [(0, [0, 0, 10, 90]), (1, [0, 90, 10, 10])]);
+    }
+}
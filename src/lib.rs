@@ -0,0 +1,15 @@
+pub mod document;
+mod config;
+mod editor;
+mod encoding;
+mod filetype;
+mod finder;
+mod hex;
+mod highlighting;
+mod history;
+mod layout;
+pub mod row;
+mod swap;
+mod syntax_tree;
+mod terminal;
+pub use editor::{Editor, Position, SearchDirection};
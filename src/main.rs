@@ -1,11 +1,6 @@
-use editor::Editor;
+use mini_editor::Editor;
 
-mod terminal;
-mod editor;
-mod document;
-mod row;
-pub use editor::Position;
 fn main(){
     let mut editor=Editor::default();
     editor.run();
-}
\ No newline at end of file
+}
@@ -1,83 +1,409 @@
-use std::cmp;
+use std::{
+    cell::{Ref, RefCell},
+    ops::Range,
+};
 
+use regex::{Captures, Regex};
 use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
 
-#[derive(Default)]
+use crate::{
+    filetype::HighlightingOptions,
+    highlighting::{self, State, Type},
+    SearchDirection,
+};
+
+// 文档中一行的只读视图，缓存每个字位的起始字节下标
+#[derive(Default, Clone)]
 pub struct Row {
     string: String,
-    len:usize
+    boundaries: Vec<usize>,
+    // 按制表符宽度缓存每个字位的起始列，末尾多一项为整行宽度，未计算时为空
+    column_starts: RefCell<(usize, Vec<usize>)>,
+    highlighting: Vec<Type>,
 }
 
 impl From<&str> for Row {
     fn from(slice: &str) -> Self {
-        let mut row=Self {
-            string: String::from(slice),
-            len:0
-        };
-        row.update_len();
-        row
+        Self::from(String::from(slice))
+    }
+}
+
+impl From<String> for Row {
+    fn from(string: String) -> Self {
+        let boundaries = UnicodeSegmentation::grapheme_indices(string.as_str(), true)
+            .map(|(index, _)| index)
+            .collect();
+        Self {
+            string,
+            boundaries,
+            column_starts: RefCell::default(),
+            highlighting: Vec::new(),
+        }
+    }
+}
+
+// 字位在 column 列处的显示宽度，制表符延伸到下一个制表位
+fn grapheme_width(grapheme: &str, column: usize, tab_width: usize) -> usize {
+    if grapheme == "\t" {
+        tab_width - column % tab_width
+    } else if let Some(caret) = caret_notation(grapheme) {
+        caret.len()
+    } else {
+        grapheme.width()
     }
 }
 
+// 行内的 ASCII 控制字符显示为 ^M 这样的形式，避免直接输出到终端
+fn caret_notation(grapheme: &str) -> Option<String> {
+    let mut chars = grapheme.chars();
+    let c = chars.next().filter(|c| c.is_ascii_control() && *c != '\t')?;
+    if chars.next().is_some() {
+        return None;
+    }
+    Some(format!("^{}", char::from(c as u8 ^ 0x40)))
+}
+
 impl Row {
-    pub fn render(&self, start: usize, end: usize) -> String {
-        let end = cmp::min(end, self.string.len());
-        let start = cmp::min(start, end);
-        // 计算unicode字位
-        let mut result=String::new();
-        let graphemes = UnicodeSegmentation::graphemes(self.string.as_str(), true).skip(start).take(end-start).collect::<Vec<&str>>();
-        for grapheme in graphemes{
-            result.push_str(grapheme)
+    // 渲染 [start, end) 区间的字位，制表符按所在列展开为空格
+    pub fn render(&self, start: usize, end: usize, tab_width: usize) -> String {
+        let mut column = self.column(start, tab_width);
+        let mut result = String::new();
+        for grapheme in self.substring(start, end).graphemes(true) {
+            let width = grapheme_width(grapheme, column, tab_width);
+            if grapheme == "\t" {
+                result.push_str(&" ".repeat(width));
+            } else if let Some(caret) = caret_notation(grapheme) {
+                result.push_str(&caret);
+            } else {
+                result.push_str(grapheme);
+            }
+            column = column.saturating_add(width);
+        }
+        result
+    }
+    // 每个字位的起始列，末尾为整行宽度；制表符宽度变化时重新计算
+    fn column_starts(&self, tab_width: usize) -> Ref<'_, [usize]> {
+        let stale = {
+            let cached = self.column_starts.borrow();
+            cached.1.is_empty() || cached.0 != tab_width
+        };
+        if stale {
+            let mut starts = Vec::with_capacity(self.len().saturating_add(1));
+            let mut column: usize = 0;
+            starts.push(column);
+            for grapheme in self.string.graphemes(true) {
+                column = column.saturating_add(grapheme_width(grapheme, column, tab_width));
+                starts.push(column);
+            }
+            *self.column_starts.borrow_mut() = (tab_width, starts);
         }
-        result    
+        Ref::map(self.column_starts.borrow(), |(_, starts)| starts.as_slice())
+    }
+    // 字位下标对应的显示列
+    pub fn column(&self, index: usize, tab_width: usize) -> usize {
+        let starts = self.column_starts(tab_width);
+        starts[index.min(self.len())]
+    }
+    // 第 index 个字位的显示宽度，行尾为 0
+    pub fn width(&self, index: usize, tab_width: usize) -> usize {
+        let starts = self.column_starts(tab_width);
+        starts
+            .get(index..=index.saturating_add(1))
+            .map_or(0, |bounds| bounds[1].saturating_sub(bounds[0]))
+    }
+    // 覆盖显示列 column 的字位下标，超出行尾时返回行长
+    pub fn index_at_column(&self, column: usize, tab_width: usize) -> usize {
+        self.column_starts(tab_width)[1..].partition_point(|&end| end <= column)
+    }
+    // 截取 [start, end) 区间的字位
+    pub fn substring(&self, start: usize, end: usize) -> String {
+        let end = end.min(self.len());
+        let start = start.min(end);
+        self.string[self.byte_index(start)..self.byte_index(end)].to_string()
+    }
+    // 按宽度折行，尽量在空白之后断开，返回每个视觉行起始的字位下标
+    pub fn wrap(&self, width: usize, tab_width: usize) -> Vec<usize> {
+        let width = width.max(1);
+        let mut starts = vec![0];
+        let mut line_column: usize = 0;
+        // 当前视觉行内最后一个可断开的位置及其所在列
+        let mut break_at = None;
+        let mut end_column: usize = 0;
+        let column_starts = self.column_starts(tab_width);
+        let graphemes = self.string.graphemes(true);
+        for (index, (grapheme, bounds)) in graphemes.zip(column_starts.windows(2)).enumerate() {
+            let line_start = starts.last().copied().unwrap_or_default();
+            let column = bounds[0];
+            end_column = bounds[1];
+            if end_column > line_column.saturating_add(width) && index > line_start {
+                let (start, start_column) = break_at.take().unwrap_or((index, column));
+                starts.push(start);
+                line_column = start_column;
+            }
+            if grapheme.chars().all(char::is_whitespace) {
+                break_at = Some((index.saturating_add(1), end_column));
+            }
+        }
+        // 正好写满最后一行时，行尾的光标落在新的一行
+        let last = starts.last().copied().unwrap_or_default();
+        if self.len() > last && end_column >= line_column.saturating_add(width) {
+            starts.push(self.len());
+        }
+        starts
+    }
+    // index 处单词的字位区间，不在单词上时只取这一个字位
+    pub fn word_bounds(&self, index: usize) -> (usize, usize) {
+        let graphemes: Vec<&str> = self.string.graphemes(true).collect();
+        let is_word = |grapheme: &&str| grapheme.chars().all(|c| c.is_alphanumeric() || c == '_');
+        if !graphemes.get(index).is_some_and(is_word) {
+            return (index, index.saturating_add(1).min(self.len()));
+        }
+        let start = graphemes[..index]
+            .iter()
+            .rposition(|grapheme| !is_word(grapheme))
+            .map_or(0, |position| position.saturating_add(1));
+        let end = graphemes[index..]
+            .iter()
+            .position(|grapheme| !is_word(grapheme))
+            .map_or(self.len(), |position| index.saturating_add(position));
+        (start, end)
+    }
+    // 从上一行行尾的状态开始计算每个字位的高亮，返回本行行尾的状态
+    pub fn highlight(&mut self, opts: &HighlightingOptions, state: State) -> State {
+        let graphemes: Vec<&str> =
+            UnicodeSegmentation::graphemes(self.string.as_str(), true).collect();
+        let (highlighting, state) = highlighting::highlight(&graphemes, opts, state);
+        self.highlighting = highlighting;
+        state
+    }
+    // 只计算本行行尾的状态，不保存高亮
+    pub fn end_state(&self, opts: &HighlightingOptions, state: State) -> State {
+        let graphemes: Vec<&str> = self.string.graphemes(true).collect();
+        highlighting::highlight(&graphemes, opts, state).1
+    }
+    // 按字节区间设置高亮，先出现的区间优先
+    pub fn set_highlighting(&mut self, ranges: impl Iterator<Item = (Range<usize>, Type)>) {
+        let mut highlighting = vec![None; self.len()];
+        for (range, hl_type) in ranges {
+            let end = self.grapheme_index(range.end).min(self.len());
+            let start = self.grapheme_index(range.start).min(end);
+            for slot in &mut highlighting[start..end] {
+                slot.get_or_insert(hl_type);
+            }
+        }
+        self.highlighting = highlighting.into_iter().map(Option::unwrap_or_default).collect();
+    }
+    pub fn highlighting(&self, index: usize) -> Type {
+        self.highlighting.get(index).copied().unwrap_or_default()
     }
     pub fn len(&self) -> usize {
-       self.len
+        self.boundaries.len()
     }
     #[allow(dead_code)]
     pub fn is_empty(&self) -> bool {
-        self.len==0
-    }
-
-    pub fn update_len(&mut self){
-        self.len = UnicodeSegmentation::graphemes(self.string.as_str(), true).collect::<Vec<&str>>().len();
-    }
-    // 插入字符
-    pub fn insert(&mut self, at:usize, c:char){
-        if at>self.len{
-            self.string.push(c);
-        }else {
-            let mut result:String=UnicodeSegmentation::graphemes(self.string.as_str(), true).take(at).collect();
-            let  remainder:String=UnicodeSegmentation::graphemes(self.string.as_str(), true).skip(at).collect();
-            result.push(c);
-            result.push_str(&remainder);
-            self.string=result
+        self.boundaries.is_empty()
+    }
+    // 按字位查找，返回匹配处的字位下标
+    pub fn find(&self, query: &str, at: usize, direction: SearchDirection) -> Option<usize> {
+        if at > self.len() || query.is_empty() {
+            return None;
         }
-        self.update_len();
-    }
-    pub fn delete(&mut self, at:usize){
-        if at>self.len(){
-            return;
-        }else {
-            let mut result:String=UnicodeSegmentation::graphemes(self.string.as_str(), true).take(at).collect();
-            let  remainder:String=UnicodeSegmentation::graphemes(self.string.as_str(), true).skip(at+1).collect();
-            result.push_str(&remainder);
-            self.string=result
+        let (start, end) = match direction {
+            SearchDirection::Forward => (at, self.len()),
+            SearchDirection::Backward => (0, at),
+        };
+        let offset = self.byte_index(start);
+        let substring = &self.string[offset..self.byte_index(end)];
+        // 跳过首尾不在字位边界上的匹配，例如只匹配到带组合字符的字位的一部分
+        let to_grapheme = |(index, matched): (usize, &str)| {
+            let end = offset + index + matched.len();
+            if end < self.string.len() && self.boundaries.binary_search(&end).is_err() {
+                return None;
+            }
+            self.boundaries.binary_search(&(offset + index)).ok()
+        };
+        match direction {
+            SearchDirection::Forward => substring.match_indices(query).find_map(to_grapheme),
+            SearchDirection::Backward => substring.rmatch_indices(query).find_map(to_grapheme),
         }
-        self.update_len();
     }
-    pub fn append(&mut self,new:&Self){
-        self.string=format!("{}{}",self.string,new.string);
-        self.update_len();
+    // 查找 at 之后的第一个非空正则匹配，返回匹配的字位区间
+    pub fn find_regex(&self, re: &Regex, at: usize) -> Option<(usize, usize)> {
+        let matched = self.captures_from(re, at)?.get(0)?;
+        Some((
+            self.grapheme_index(matched.start()),
+            self.grapheme_index(matched.end()),
+        ))
     }
-    pub fn split(&mut self, at: usize) -> Self {
-        let beginning: String = UnicodeSegmentation::graphemes(self.string.as_str(), true).take(at).collect();
-        let remainder: String = UnicodeSegmentation::graphemes(self.string.as_str(), true).skip(at).collect();
-        self.string = beginning;
-        self.update_len();
-        Self::from(&remainder[..])
+    // 用捕获组展开替换文本
+    pub fn expand_regex(&self, re: &Regex, at: usize, replacement: &str) -> Option<String> {
+        let captures = self.captures_from(re, at)?;
+        let mut result = String::new();
+        captures.expand(replacement, &mut result);
+        Some(result)
     }
-    pub fn as_bytes(&self)->&[u8]{
-        self.string.as_bytes()
+    fn captures_from<'a>(&'a self, re: &Regex, at: usize) -> Option<Captures<'a>> {
+        if at > self.len() {
+            return None;
+        }
+        // 从 at 处开始匹配，^ 与 \b 等仍按整行判断
+        let mut byte_index = self.byte_index(at);
+        loop {
+            let captures = re.captures_at(&self.string, byte_index)?;
+            let matched = captures.get(0)?;
+            if !matched.is_empty() {
+                return Some(captures);
+            }
+            // 跳过空匹配，从下一个字符继续
+            let next = self.string[matched.start()..].chars().next()?;
+            byte_index = matched.start() + next.len_utf8();
+        }
+    }
+    // 字位下标转换为字节下标
+    pub fn byte_index(&self, grapheme_index: usize) -> usize {
+        self.boundaries
+            .get(grapheme_index)
+            .copied()
+            .unwrap_or(self.string.len())
+    }
+    // 字节下标转换为字位下标
+    pub fn grapheme_index(&self, byte_index: usize) -> usize {
+        self.boundaries.partition_point(|index| *index < byte_index)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn find_returns_grapheme_index() {
+        let row = Row::from("日本語 text text");
+        assert_eq!(row.find("text", 0, SearchDirection::Forward), Some(4));
+        assert_eq!(row.find("text", 5, SearchDirection::Forward), Some(9));
+        assert_eq!(row.find("text", row.len(), SearchDirection::Backward), Some(9));
+        assert_eq!(row.find("text", 9, SearchDirection::Backward), Some(4));
+        assert_eq!(row.find("missing", 0, SearchDirection::Forward), None);
+    }
+
+    #[test]
+    fn find_skips_matches_inside_graphemes() {
+        // 第一个 e 带有组合重音符，只是字位的一部分
+        let row = Row::from("e\u{301} e");
+        assert_eq!(row.len(), 3);
+        assert_eq!(row.find("e", 0, SearchDirection::Forward), Some(2));
+        assert_eq!(row.find("e", row.len(), SearchDirection::Backward), Some(2));
+        // 制表符之后的组合字符自成一个字位
+        let row = Row::from("e\u{301}a\t\u{301}a");
+        assert_eq!(row.find("\u{301}a", 0, SearchDirection::Forward), Some(3));
+        let row = Row::from("\u{301}a e\u{301}a");
+        assert_eq!(row.find("\u{301}a", row.len(), SearchDirection::Backward), Some(0));
+    }
+
+    #[test]
+    fn find_regex_starts_at_the_position() {
+        let re = Regex::new("aa").unwrap();
+        let row = Row::from("aaa");
+        assert_eq!(row.find_regex(&re, 0), Some((0, 2)));
+        // 与前一个匹配重叠的匹配
+        assert_eq!(row.find_regex(&re, 1), Some((1, 3)));
+        assert_eq!(row.find_regex(&re, 2), None);
+        assert_eq!(row.find_regex(&re, 4), None);
+        let re = Regex::new("^a").unwrap();
+        assert_eq!(Row::from("aa").find_regex(&re, 1), None);
+    }
+
+    #[test]
+    fn find_regex_skips_empty_matches() {
+        let re = Regex::new("x*").unwrap();
+        assert_eq!(Row::from("abxx").find_regex(&re, 0), Some((2, 4)));
+        assert_eq!(Row::from("ab").find_regex(&re, 0), None);
+        let re = Regex::new("b*").unwrap();
+        assert_eq!(Row::from("日b").find_regex(&re, 0), Some((1, 2)));
+    }
+
+    #[test]
+    fn expand_regex_fills_in_groups() {
+        let re = Regex::new(r"(\w+)@(\w+)").unwrap();
+        let row = Row::from("x@y a@b");
+        assert_eq!(row.expand_regex(&re, 0, "$2 at $1").as_deref(), Some("y at x"));
+        assert_eq!(row.expand_regex(&re, 1, "$2 at $1").as_deref(), Some("b at a"));
+        assert_eq!(row.expand_regex(&re, 5, "$2"), None);
+    }
+
+    #[test]
+    fn control_characters_render_in_caret_notation() {
+        let row = Row::from("a\rb\u{7f}\tc");
+        assert_eq!(row.render(0, row.len(), 4), "a^Mb^?  c");
+        assert_eq!(row.column(2, 4), 3);
+        assert_eq!(row.index_at_column(2, 4), 1);
+    }
+
+    #[test]
+    fn tabs_stop_at_multiples_of_the_tab_width() {
+        let row = Row::from("a\tbc\td");
+        assert_eq!(row.column(1, 4), 1);
+        assert_eq!(row.width(1, 4), 3);
+        assert_eq!(row.column(2, 4), 4);
+        assert_eq!(row.width(4, 4), 2);
+        assert_eq!(row.column(5, 4), 8);
+        assert_eq!(row.render(0, row.len(), 4), "a   bc  d");
+        // 换一个制表符宽度后重新计算
+        assert_eq!(row.column(5, 8), 16);
+        assert_eq!(row.width(row.len(), 8), 0);
+        assert_eq!(row.index_at_column(5, 4), 3);
+    }
+
+    #[test]
+    fn wide_characters_take_two_columns() {
+        let row = Row::from("a中文b");
+        assert_eq!(row.column(2, 4), 3);
+        assert_eq!(row.width(1, 4), 2);
+        assert_eq!(row.column(row.len(), 4), 6);
+        assert_eq!(row.column(row.len().saturating_add(3), 4), 6);
+    }
+
+    #[test]
+    fn index_at_column_inside_a_wide_character() {
+        let row = Row::from("a中文b");
+        assert_eq!(row.index_at_column(0, 4), 0);
+        assert_eq!(row.index_at_column(1, 4), 1);
+        assert_eq!(row.index_at_column(2, 4), 1);
+        assert_eq!(row.index_at_column(4, 4), 2);
+        assert_eq!(row.index_at_column(5, 4), 3);
+        assert_eq!(row.index_at_column(6, 4), 4);
+        assert_eq!(row.index_at_column(100, 4), 4);
+    }
+
+    #[test]
+    fn wrap_breaks_after_whitespace() {
+        let row = Row::from("hello world");
+        assert_eq!(row.wrap(8, 4), vec![0, 6]);
+        let row = Row::from("aa bb cc dd");
+        assert_eq!(row.wrap(6, 4), vec![0, 6]);
+    }
+
+    #[test]
+    fn wrap_breaks_inside_a_long_word() {
+        let row = Row::from("abcdefghij");
+        assert_eq!(row.wrap(4, 4), vec![0, 4, 8]);
+        // 空白之后的长单词从自己的行开始再硬断
+        let row = Row::from("a bcdefgh");
+        assert_eq!(row.wrap(4, 4), vec![0, 2, 6]);
+    }
+
+    #[test]
+    fn wrap_moves_wide_character_at_the_edge() {
+        let row = Row::from("ab中文");
+        assert_eq!(row.wrap(3, 4), vec![0, 2, 3]);
+    }
+
+    #[test]
+    fn wrap_adds_a_line_when_the_last_line_is_full() {
+        assert_eq!(Row::from("abcd").wrap(4, 4), vec![0, 4]);
+        assert_eq!(Row::from("abcdefgh").wrap(4, 4), vec![0, 4, 8]);
+        assert_eq!(Row::from("abc").wrap(4, 4), vec![0]);
+        assert_eq!(Row::from("").wrap(4, 4), vec![0]);
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,265 @@
+use std::{
+    fmt::Write,
+    fs, io,
+    path::{Path, PathBuf},
+    process,
+    sync::mpsc::{self, Receiver, Sender},
+    thread,
+};
+
+// 交换文件首行的标记，后跟写入进程的 pid
+const MAGIC: &str = "MINI-EDITOR-SWAP";
+// 比较结果中保留的未修改上下文行数
+const CONTEXT_LINES: usize = 3;
+// 超过这个规模时不再逐行求最长公共子序列
+const MAX_DIFF_CELLS: usize = 4_000_000;
+
+// 上次异常退出留下的交换文件
+pub struct Swap {
+    pub pid: u32,
+    pub text: String,
+}
+
+// 与原文件同目录的隐藏文件 .name.swp
+pub fn path(file_name: &str) -> PathBuf {
+    let path = Path::new(file_name);
+    let name = path
+        .file_name()
+        .and_then(|name| name.to_str())
+        .unwrap_or("untitled");
+    path.with_file_name(format!(".{name}.swp"))
+}
+
+// 在后台线程执行的交换文件写入或删除
+pub type Job = Box<dyn FnOnce() -> Result<(), io::Error> + Send>;
+
+// 按提交顺序在后台线程执行任务，写大文件时不阻塞输入
+pub struct Writer {
+    jobs: Sender<Job>,
+    errors: Receiver<io::Error>,
+}
+
+impl Default for Writer {
+    fn default() -> Self {
+        let (jobs, receiver) = mpsc::channel::<Job>();
+        let (sender, errors) = mpsc::channel();
+        thread::spawn(move || {
+            for job in receiver {
+                if let Err(error) = job() {
+                    let _ = sender.send(error);
+                }
+            }
+        });
+        Self { jobs, errors }
+    }
+}
+
+impl Writer {
+    pub fn submit(&self, job: Job) {
+        let _ = self.jobs.send(job);
+    }
+    // 等待已提交的任务全部完成，之后才能同步地删除交换文件
+    pub fn flush(&self) {
+        let (done, wait) = mpsc::channel();
+        self.submit(Box::new(move || {
+            let _ = done.send(());
+            Ok(())
+        }));
+        let _ = wait.recv();
+    }
+    // 取出后台任务出的错
+    pub fn errors(&self) -> impl Iterator<Item = io::Error> + '_ {
+        self.errors.try_iter()
+    }
+}
+
+pub fn header() -> String {
+    format!("{MAGIC} {}\n", process::id())
+}
+
+// 读取并校验交换文件，不存在或不是本编辑器写的时返回 None
+pub fn read(file_name: &str) -> Option<Swap> {
+    let contents = fs::read_to_string(path(file_name)).ok()?;
+    let (header, text) = contents.split_once('\n')?;
+    let pid = header.strip_prefix(MAGIC)?.trim().parse().ok()?;
+    Some(Swap {
+        pid,
+        text: text.to_string(),
+    })
+}
+
+pub fn remove(file_name: &str) -> Result<(), io::Error> {
+    match fs::remove_file(path(file_name)) {
+        Err(error) if error.kind() != io::ErrorKind::NotFound => Err(error),
+        _ => Ok(()),
+    }
+}
+
+// 写交换文件的进程是否还在运行，只在有 /proc 的系统上可靠
+pub fn is_running(pid: u32) -> bool {
+    pid != process::id() && Path::new("/proc").join(pid.to_string()).exists()
+}
+
+// 逐行比较，删除的行以 - 开头，新增的行以 + 开头，较长的未修改部分折叠为 ...
+pub fn diff(old: &str, new: &str) -> String {
+    let old: Vec<&str> = old.lines().collect();
+    let new: Vec<&str> = new.lines().collect();
+    let prefix = old.iter().zip(&new).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let old_middle = &old[prefix..old.len() - suffix];
+    let new_middle = &new[prefix..new.len() - suffix];
+    let mut lines: Vec<(char, &str)> = old[..prefix].iter().map(|line| (' ', *line)).collect();
+    lines.extend(diff_lines(old_middle, new_middle));
+    lines.extend(old[old.len() - suffix..].iter().map(|line| (' ', *line)));
+    let mut result = String::new();
+    let push = |result: &mut String, lines: &[(char, &str)]| {
+        for (mark, line) in lines {
+            let _ = writeln!(result, "{mark} {line}");
+        }
+    };
+    let mut index = 0;
+    while index < lines.len() {
+        if lines[index].0 != ' ' {
+            push(&mut result, &lines[index..=index]);
+            index += 1;
+            continue;
+        }
+        let run = lines[index..].iter().take_while(|(mark, _)| *mark == ' ').count();
+        // 首尾只保留与修改相邻的上下文
+        let keep_before = if index == 0 { 0 } else { CONTEXT_LINES };
+        let keep_after = if index + run == lines.len() { 0 } else { CONTEXT_LINES };
+        if run > keep_before + keep_after {
+            push(&mut result, &lines[index..index + keep_before]);
+            let _ = writeln!(result, "... {} unchanged lines", run - keep_before - keep_after);
+            push(&mut result, &lines[index + run - keep_after..index + run]);
+        } else {
+            push(&mut result, &lines[index..index + run]);
+        }
+        index += run;
+    }
+    result
+}
+
+// 用最长公共子序列求出两段文本的逐行差异
+fn diff_lines<'a>(old: &[&'a str], new: &[&'a str]) -> Vec<(char, &'a str)> {
+    if old.len().saturating_mul(new.len()) > MAX_DIFF_CELLS {
+        let removed = old.iter().map(|line| ('-', *line));
+        return removed.chain(new.iter().map(|line| ('+', *line))).collect();
+    }
+    // lengths[i][j] 为 old[i..] 与 new[j..] 的最长公共子序列长度
+    let mut lengths = vec![vec![0usize; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            lengths[i][j] = if old[i] == new[j] {
+                lengths[i + 1][j + 1] + 1
+            } else {
+                lengths[i + 1][j].max(lengths[i][j + 1])
+            };
+        }
+    }
+    let (mut i, mut j) = (0, 0);
+    let mut lines = Vec::new();
+    while i < old.len() || j < new.len() {
+        if i < old.len() && j < new.len() && old[i] == new[j] {
+            lines.push((' ', old[i]));
+            i += 1;
+            j += 1;
+        } else if i < old.len() && (j == new.len() || lengths[i + 1][j] >= lengths[i][j + 1]) {
+            lines.push(('-', old[i]));
+            i += 1;
+        } else {
+            lines.push(('+', new[j]));
+            j += 1;
+        }
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn numbered(range: std::ops::Range<usize>) -> String {
+        range.map(|n| format!("{n}\n")).collect()
+    }
+
+    #[test]
+    fn path_is_hidden_next_to_the_file() {
+        assert_eq!(path("dir/file.txt"), PathBuf::from("dir/.file.txt.swp"));
+        assert_eq!(path("file.txt"), PathBuf::from(".file.txt.swp"));
+    }
+
+    #[test]
+    fn writer_runs_jobs_in_order_and_reports_errors() {
+        use std::sync::{Arc, Mutex};
+
+        let writer = Writer::default();
+        let done = Arc::new(Mutex::new(Vec::new()));
+        for n in 0..3 {
+            let done = Arc::clone(&done);
+            writer.submit(Box::new(move || {
+                done.lock().unwrap().push(n);
+                Ok(())
+            }));
+        }
+        writer.submit(Box::new(|| Err(io::Error::other("disk full"))));
+        writer.flush();
+        assert_eq!(*done.lock().unwrap(), vec![0, 1, 2]);
+        let errors: Vec<_> = writer.errors().collect();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].to_string(), "disk full");
+    }
+
+    #[test]
+    fn identical_texts_have_no_changes() {
+        assert_eq!(diff("a\nb\n", "a\nb\n"), "... 2 unchanged lines\n");
+        assert_eq!(diff("", ""), "");
+    }
+
+    #[test]
+    fn changed_line_shows_deletion_before_insertion() {
+        assert_eq!(diff("a\nb\nc\n", "a\nB\nc\n"), "  a\n- b\n+ B\n  c\n");
+    }
+
+    #[test]
+    fn insertions_and_deletions_keep_common_lines() {
+        assert_eq!(diff("a\nb\nc\n", "a\nc\nd\n"), "  a\n- b\n  c\n+ d\n");
+        assert_eq!(diff("", "x\n"), "+ x\n");
+        assert_eq!(diff("x\n", ""), "- x\n");
+    }
+
+    #[test]
+    fn long_unchanged_runs_are_folded_around_context() {
+        let old = numbered(0..20);
+        let new = old.replace("10\n", "ten\n");
+        assert_eq!(
+            diff(&old, &new),
+            "... 7 unchanged lines\n  7\n  8\n  9\n- 10\n+ ten\n  11\n  12\n  13\n... 6 unchanged lines\n"
+        );
+    }
+
+    #[test]
+    fn short_unchanged_runs_between_changes_are_kept() {
+        let old = numbered(0..8);
+        let new = old.replace("0\n", "zero\n").replace("7\n", "seven\n");
+        assert_eq!(
+            diff(&old, &new),
+            "- 0\n+ zero\n  1\n  2\n  3\n  4\n  5\n  6\n- 7\n+ seven\n"
+        );
+    }
+
+    #[test]
+    fn huge_diffs_fall_back_to_replacing_everything() {
+        let old = numbered(0..3000);
+        let new = numbered(3000..6000);
+        let result = diff(&old, &new);
+        assert!(result.starts_with("- 0\n"));
+        assert_eq!(result.lines().filter(|line| line.starts_with('-')).count(), 3000);
+        assert_eq!(result.lines().filter(|line| line.starts_with('+')).count(), 3000);
+    }
+}
@@ -0,0 +1,116 @@
+use std::ops::Range;
+
+use ropey::Rope;
+use streaming_iterator::StreamingIterator;
+use tree_sitter::{InputEdit, Node, Parser, Point, Query, QueryCursor, Tree};
+
+use crate::highlighting::Type;
+
+// 函数一类的节点，用于跳转到所在函数
+const FUNCTION_KINDS: &[&str] = &["function_item", "function_signature_item", "closure_expression"];
+
+// 基于 tree-sitter 的语法树，随文档编辑增量更新
+pub struct SyntaxTree {
+    parser: Parser,
+    tree: Option<Tree>,
+    query: Query,
+    // 编辑后尚未重新解析
+    stale: bool,
+}
+
+impl SyntaxTree {
+    // 按文件类型名创建，没有对应语法时返回 None
+    pub fn new(file_type: &str) -> Option<Self> {
+        let (language, highlights) = match file_type {
+            "Rust" => (
+                tree_sitter::Language::from(tree_sitter_rust::LANGUAGE),
+                tree_sitter_rust::HIGHLIGHTS_QUERY,
+            ),
+            _ => return None,
+        };
+        let mut parser = Parser::new();
+        parser.set_language(&language).ok()?;
+        let query = Query::new(&language, highlights).ok()?;
+        Some(Self {
+            parser,
+            tree: None,
+            query,
+            stale: true,
+        })
+    }
+    pub fn edit(&mut self, edit: &InputEdit) {
+        if let Some(tree) = &mut self.tree {
+            tree.edit(edit);
+        }
+        self.stale = true;
+    }
+    // 复用旧树增量解析
+    fn parse(&mut self, text: &Rope) -> Option<&Tree> {
+        if self.stale {
+            self.tree = self.parser.parse_with_options(
+                &mut |byte, _| {
+                    if byte >= text.len_bytes() {
+                        return &[][..];
+                    }
+                    let (chunk, chunk_byte, _, _) = text.chunk_at_byte(byte);
+                    &chunk.as_bytes()[byte - chunk_byte..]
+                },
+                self.tree.as_ref(),
+                None,
+            );
+            self.stale = false;
+        }
+        self.tree.as_ref()
+    }
+    // 字节区间内各节点的高亮，先匹配到的优先
+    pub fn highlights(&mut self, text: &Rope, range: Range<usize>) -> Vec<(Range<usize>, Type)> {
+        let Some(tree) = self.parse(text).cloned() else {
+            return Vec::new();
+        };
+        let mut cursor = QueryCursor::new();
+        cursor.set_byte_range(range);
+        let names = self.query.capture_names();
+        let mut highlights = Vec::new();
+        let mut captures = cursor.captures(&self.query, tree.root_node(), |node: Node| {
+            text.byte_slice(node.byte_range()).chunks().map(str::as_bytes)
+        });
+        while let Some((query_match, index)) = captures.next() {
+            let capture = query_match.captures[*index];
+            let hl_type = match names[capture.index as usize] {
+                name if name.starts_with("comment") => Type::Comment,
+                "string" | "escape" => Type::String,
+                "keyword" | "attribute" => Type::Keyword,
+                "type" | "type.builtin" | "constructor" => Type::Builtin,
+                "constant" | "constant.builtin" => Type::Number,
+                "operator" => Type::Operator,
+                _ => continue,
+            };
+            highlights.push((capture.node.byte_range(), hl_type));
+        }
+        highlights
+    }
+    // 严格包含 [start, end) 的最小具名节点
+    pub fn enclosing(&mut self, text: &Rope, start: usize, end: usize) -> Option<Range<usize>> {
+        let tree = self.parse(text)?;
+        let mut node = tree.root_node().descendant_for_byte_range(start, end)?;
+        while !node.is_named() || node.byte_range() == (start..end) {
+            node = node.parent()?;
+        }
+        Some(node.byte_range())
+    }
+    // byte 之前开始的最近一层函数
+    pub fn parent_function(&mut self, text: &Rope, byte: usize) -> Option<usize> {
+        let tree = self.parse(text)?;
+        let mut node = tree.root_node().descendant_for_byte_range(byte, byte)?;
+        while !(FUNCTION_KINDS.contains(&node.kind()) && node.start_byte() < byte) {
+            node = node.parent()?;
+        }
+        Some(node.start_byte())
+    }
+}
+
+// 字节下标所在的行列，列以字节计
+pub fn point(text: &Rope, byte: usize) -> Point {
+    let row = text.byte_to_line(byte);
+    Point::new(row, byte - text.line_to_byte(row))
+}
@@ -1,18 +1,52 @@
-use std::io::{self, stdout, Stdout, Write};
+use std::{
+    io::{self, stdout, Stdout, Write},
+    time::Duration,
+};
 
+use base64::{engine::general_purpose::STANDARD, Engine};
 use crossterm::{
     cursor,
-    event::{read, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers},
-    style::{Color, ResetColor, SetBackgroundColor, SetForegroundColor},
-    terminal, QueueableCommand,
+    event::{
+        self, read, DisableBracketedPaste, DisableMouseCapture, EnableBracketedPaste,
+        EnableMouseCapture, KeyCode, KeyEvent, KeyEventKind, KeyModifiers, MouseButton, MouseEvent,
+        MouseEventKind,
+    },
+    style::{Attribute, Color, ResetColor, SetAttribute, SetBackgroundColor, SetForegroundColor},
+    terminal, ExecutableCommand, QueueableCommand,
 };
 
 use crate::Position;
 
+// 超过这个时长没有输入时返回 Event::Idle
+const IDLE_TIMEOUT: Duration = Duration::from_secs(2);
+
 pub struct Size {
     pub width: u16,
     pub height: u16,
 }
+
+impl Size {
+    // 底部两行留给状态栏与消息栏
+    fn from_terminal(width: u16, height: u16) -> Self {
+        Self {
+            width,
+            height: height.saturating_sub(2),
+        }
+    }
+}
+// 编辑器关心的终端输入
+pub enum Event {
+    Key(KeyCode, KeyModifiers),
+    // 括号粘贴模式下一次性收到的粘贴内容
+    Paste(String),
+    // 终端大小已改变，Terminal::size 已更新
+    Resize,
+    // 鼠标按下、拖动与滚轮，位置为屏幕坐标
+    Mouse(MouseEventKind, Position),
+    // 一段时间没有输入，可以做后台工作
+    Idle,
+}
+
 pub struct Terminal {
     size: Size,
     _stdout: Stdout,
@@ -20,15 +54,14 @@ pub struct Terminal {
 
 impl Terminal {
     pub fn default() -> Result<Self, std::io::Error> {
-        let size = terminal::size()?;
+        let (width, height) = terminal::size()?;
         terminal::enable_raw_mode()?;
+        let mut _stdout = stdout();
+        _stdout.execute(EnableBracketedPaste)?;
+        _stdout.execute(EnableMouseCapture)?;
         Ok(Self {
-            size: Size {
-                width: size.0,
-                //
-                height: size.1.saturating_sub(2),
-            },
-            _stdout: stdout(),
+            size: Size::from_terminal(width, height),
+            _stdout,
         })
     }
     pub fn size(&self) -> &Size {
@@ -63,6 +96,20 @@ impl Terminal {
         self._stdout.queue(ResetColor)?;
         Ok(())
     }
+    // 反色显示，ResetColor 不会清除该属性
+    pub fn set_inverted(&mut self) -> Result<(), std::io::Error> {
+        self._stdout.queue(SetAttribute(Attribute::Reverse))?;
+        Ok(())
+    }
+    pub fn reset_inverted(&mut self) -> Result<(), std::io::Error> {
+        self._stdout.queue(SetAttribute(Attribute::NoReverse))?;
+        Ok(())
+    }
+    // 通过 OSC 52 把文本复制到宿主终端的剪贴板，SSH 下同样有效
+    pub fn set_clipboard(&mut self, text: &str) -> Result<(), std::io::Error> {
+        write!(self._stdout, "\x1b]52;c;{}\x07", STANDARD.encode(text))?;
+        Ok(())
+    }
     pub fn cursor_position(&mut self, position: &Position) -> Result<(), io::Error> {
         let Position { x, y } = position;
         let x = x.saturating_add(0);
@@ -84,17 +131,52 @@ impl Terminal {
         self._stdout.flush()?;
         Ok(())
     }
-    pub fn read_key(&self) -> Result<(KeyCode, KeyModifiers), std::io::Error> {
+    pub fn read_key(&mut self) -> Result<Event, std::io::Error> {
         loop {
-            if let Some(Event::Key(KeyEvent {
-                code,
-                modifiers,
-                kind: KeyEventKind::Press,
-                state: _,
-            })) = read().into_iter().next()
-            {
-                return Ok((code, modifiers));
+            if !event::poll(IDLE_TIMEOUT)? {
+                return Ok(Event::Idle);
+            }
+            match read()? {
+                event::Event::Key(KeyEvent {
+                    code,
+                    modifiers,
+                    kind: KeyEventKind::Press,
+                    state: _,
+                }) => return Ok(Event::Key(code, modifiers)),
+                event::Event::Paste(text) => return Ok(Event::Paste(text)),
+                // 只关心左键与滚轮，忽略移动和松开
+                event::Event::Mouse(MouseEvent {
+                    kind:
+                        kind @ (MouseEventKind::Down(MouseButton::Left)
+                        | MouseEventKind::Drag(MouseButton::Left)
+                        | MouseEventKind::ScrollUp
+                        | MouseEventKind::ScrollDown),
+                    column,
+                    row,
+                    modifiers: _,
+                }) => {
+                    return Ok(Event::Mouse(
+                        kind,
+                        Position {
+                            x: column as usize,
+                            y: row as usize,
+                        },
+                    ))
+                }
+                event::Event::Resize(width, height) => {
+                    self.size = Size::from_terminal(width, height);
+                    return Ok(Event::Resize);
+                }
+                _ => (),
             }
         }
     }
 }
+
+impl Drop for Terminal {
+    fn drop(&mut self) {
+        let _ = self._stdout.execute(DisableMouseCapture);
+        let _ = self._stdout.execute(DisableBracketedPaste);
+        let _ = terminal::disable_raw_mode();
+    }
+}